@@ -1,5 +1,6 @@
 use std::fs::File;
 use std::io::{self, BufWriter, Write};
+use std::path::Path;
 
 use anyhow::Context;
 use colored::Colorize;
@@ -10,9 +11,12 @@ use itertools::Itertools;
 use pdbtbx::{save_pdb, Atom, ContainsAtomConformer, ContainsAtomConformerResidue};
 use rayon::iter::ParallelIterator;
 
-use crate::functions;
+use crate::functions::{self, Severity};
 use crate::options::{Distance, MeasureTarget, Mode, Output, Region};
+use crate::prms::ForceField;
 use crate::revertable::{EditOp, Revertable};
+use crate::rtf::Topology;
+use crate::selection::{ordered_definitions, Definitions};
 
 // Run function that handles the logic of when to call which function given an enum with the
 // command line options. Hands all occurring errors to caller.
@@ -20,12 +24,13 @@ pub fn dispatch(
     mode: &Mode,
     pdb: &mut pdbtbx::PDB,
     pdb_path: &str,
+    definitions: &mut Definitions,
 ) -> Result<Option<Revertable>, anyhow::Error> {
     let mut edit_op: Option<Revertable> = None;
 
     match mode {
         Mode::Query { input } => {
-            let atomlist = functions::get_atomlist_from_input(input, pdb, None)?;
+            let atomlist = functions::get_atomlist_from_input(input, pdb, None, definitions)?;
             let (table, res) = functions::query_atoms(pdb, &atomlist)?;
             if let Some(s) = res {
                 writeln!(io::stdout(), "{}", s)
@@ -37,6 +42,16 @@ pub fn dispatch(
             region,
             target,
             distance,
+            boundary,
+            multiplicity,
+            topology,
+            completeness,
+            rtf,
+            prms,
+            metalsite,
+            hbonds,
+            environment,
+            sasa,
         } => {
             let (basic_table, detailed_table) = functions::analyze(pdb, *region, *target)?;
             writeln!(io::stdout(), "{}", basic_table).context("Failed to write table to stdout")?;
@@ -64,6 +79,115 @@ pub fn dispatch(
                 }
                 writeln!(io::stdout(), "{}", table).context("Failed to print table to stdout")?;
             }
+
+            let forcefield = prms.as_deref().map(ForceField::from_file).transpose()?;
+            if let Some(f) = &forcefield {
+                let (table, mismatches) = functions::analyze_forcefield(pdb, f)?;
+                writeln!(io::stdout(), "\nForce Field Validation")
+                    .context("Failed to print force field validation to stdout.")?;
+                writeln!(io::stdout(), "{}", table).context("Failed to print table to stdout")?;
+                if let Some(t) = mismatches {
+                    writeln!(io::stdout(), "{}", t).context("Failed to print table to stdout")?;
+                }
+            }
+
+            if *boundary {
+                let table = functions::analyze_boundary(pdb, forcefield.as_ref())?;
+                writeln!(io::stdout(), "\nBoundary Analysis")
+                    .context("Failed to print boundary analysis to stdout.")?;
+                writeln!(io::stdout(), "{}", table).context("Failed to print table to stdout")?;
+            }
+
+            let read_topology = || -> Result<Topology, anyhow::Error> {
+                match rtf {
+                    Some(path) => {
+                        let topology = Topology::from_file(path)?;
+                        for warning in &topology.warnings {
+                            writeln!(io::stderr(), "{}", warning.yellow())
+                                .context("Failed to print warning to stderr")?;
+                        }
+                        Ok(topology)
+                    }
+                    None => Ok(Topology::charmm36_protein()),
+                }
+            };
+
+            if *topology {
+                let table = functions::analyze_topology(pdb, &read_topology()?)?;
+                writeln!(io::stdout(), "\nTopology Analysis")
+                    .context("Failed to print topology analysis to stdout.")?;
+                writeln!(io::stdout(), "{}", table).context("Failed to print table to stdout")?;
+            }
+
+            if *completeness {
+                let (summary_table, residue_table, gap_table) =
+                    functions::analyze_completeness(pdb, &read_topology()?)?;
+                writeln!(io::stdout(), "\nCompleteness Analysis")
+                    .context("Failed to print completeness analysis to stdout.")?;
+                writeln!(io::stdout(), "{}", summary_table)
+                    .context("Failed to print table to stdout")?;
+                for table in residue_table.iter().chain(&gap_table) {
+                    writeln!(io::stdout(), "{}", table)
+                        .context("Failed to print table to stdout")?;
+                }
+            }
+
+            if let Some(m) = *multiplicity {
+                let (group_table, table) =
+                    functions::analyze_charge(pdb, m, &read_topology()?, forcefield.as_ref())?;
+                writeln!(io::stdout(), "\nCharge Analysis")
+                    .context("Failed to print charge analysis to stdout.")?;
+                writeln!(io::stdout(), "{}\n{}", group_table, table)
+                    .context("Failed to print tables to stdout")?;
+            }
+
+            if let Some(input) = metalsite {
+                let atoms = functions::get_atomlist_from_input(input, pdb, None, definitions)?;
+                let table = functions::analyze_metalsite(pdb, &atoms)?;
+                writeln!(io::stdout(), "\nMetal Site Analysis")
+                    .context("Failed to print metal site analysis to stdout.")?;
+                writeln!(io::stdout(), "{}", table).context("Failed to print table to stdout")?;
+            }
+
+            if let Some(input) = hbonds {
+                let atoms = input
+                    .as_deref()
+                    .map(|i| functions::get_atomlist_from_input(i, pdb, None, definitions))
+                    .transpose()?;
+                let table = functions::analyze_hbonds(pdb, atoms.as_deref())?;
+                writeln!(io::stdout(), "\nHydrogen Bond Analysis")
+                    .context("Failed to print hydrogen bond analysis to stdout.")?;
+                writeln!(io::stdout(), "{}", table).context("Failed to print table to stdout")?;
+            }
+
+            if let Some((input, radius)) = environment {
+                let atoms = functions::get_atomlist_from_input(input, pdb, None, definitions)?;
+                let (table, bridges) = functions::analyze_environment(pdb, &atoms, *radius)?;
+                writeln!(io::stdout(), "\nCharged Environment Analysis")
+                    .context("Failed to print environment analysis to stdout.")?;
+                writeln!(io::stdout(), "{}", table).context("Failed to print table to stdout")?;
+                if let Some(t) = bridges {
+                    writeln!(io::stdout(), "\nSalt Bridges")
+                        .context("Failed to print salt bridges to stdout.")?;
+                    writeln!(io::stdout(), "{}", t).context("Failed to print table to stdout")?;
+                }
+            }
+
+            if let Some((input, probe_radius)) = sasa {
+                let atoms = input
+                    .as_deref()
+                    .map(|i| functions::get_atomlist_from_input(i, pdb, None, definitions))
+                    .transpose()?;
+                let (region_table, residue_table, atom_table) =
+                    functions::analyze_sasa(pdb, atoms.as_deref(), *probe_radius)?;
+                writeln!(io::stdout(), "\nSolvent Accessible Surface Area")
+                    .context("Failed to print surface area analysis to stdout.")?;
+                writeln!(io::stdout(), "{}\n{}", region_table, residue_table)
+                    .context("Failed to print tables to stdout")?;
+                if let Some(t) = atom_table {
+                    writeln!(io::stdout(), "{}", t).context("Failed to print table to stdout")?;
+                }
+            }
         }
         Mode::Measure { measure_target } => match measure_target {
             MeasureTarget::Atoms(atoms) => {
@@ -132,10 +256,42 @@ pub fn dispatch(
                 .context("Failed to print table to stdout")?;
             }
         },
+        Mode::Add {
+            around_qm: Some(shell),
+            ..
+        } => {
+            let active = functions::get_active_around_qm(pdb, shell.radius, shell.max_atoms)?;
+
+            // The new active region replaces the old one so that the atom limit applies to the
+            // whole region.
+            let previous = functions::get_atomlist(pdb, Region::Active).unwrap_or_default();
+            let actual_previous =
+                functions::edit_atoms_checked(pdb, &previous, "Remove", Region::Active);
+            let actual_self = functions::edit_atoms_checked(pdb, &active, "Add", Region::Active)?;
+
+            edit_op = if let Ok(actual) = actual_previous {
+                Some(Revertable::Many(vec![
+                    EditOp::ToRemove {
+                        region: Region::Active,
+                        atoms: actual,
+                    },
+                    EditOp::ToAdd {
+                        region: Region::Active,
+                        atoms: actual_self,
+                    },
+                ]))
+            } else {
+                Some(Revertable::One(EditOp::ToAdd {
+                    region: Region::Active,
+                    atoms: actual_self,
+                }))
+            };
+        }
         Mode::Add {
             region,
             selection,
             partial,
+            around_qm: None,
         }
         | Mode::Remove {
             region,
@@ -145,7 +301,12 @@ pub fn dispatch(
             let mut input_list: Vec<usize> = Vec::new();
             match selection {
                 Some(s) => {
-                    input_list.extend(functions::get_atomlist_from_input(s, pdb, *partial)?);
+                    input_list.extend(functions::get_atomlist_from_input(
+                        s,
+                        pdb,
+                        *partial,
+                        definitions,
+                    )?);
                 }
                 None => {
                     if mode.to_string() == "Remove" && *region == None {
@@ -270,60 +431,143 @@ pub fn dispatch(
                 }
             }
         }
-        Mode::Write { output, state } => match output {
-            None => {
-                if *state {
-                    let stdout = io::stdout();
-                    let mut handle = stdout.lock();
-
-                    writeln!(handle, "R")?;
-                    for (region, string) in [Region::QM1, Region::QM2, Region::Active]
-                        .into_iter()
-                        .zip(["-q", "-o", "-a"].into_iter())
-                    {
-                        if let Ok(l) = functions::get_atomlist(pdb, region) {
-                            writeln!(
-                                handle,
-                                "A {} id {}",
-                                string,
-                                l.into_iter().map(|n| n.to_string()).join(",")
-                            )?;
-                        };
-                    }
-                    writeln!(handle, "W -w")?;
-                } else {
-                    functions::print_pdb_to_stdout(pdb)?;
+        Mode::Write {
+            output,
+            state,
+            orca,
+            xyz,
+            check,
+        } => {
+            if *check {
+                let diagnostics = functions::check_regions(pdb, functions::MAX_ACTIVE_ATOMS);
+                if !diagnostics.is_empty() {
+                    // Diagnostics must not end up in output written to stdout
+                    writeln!(io::stderr(), "{}", functions::check_table(&diagnostics))
+                        .context("Failed to write table to stderr")?;
                 }
+                ensure!(
+                    diagnostics
+                        .iter()
+                        .all(|d| d.rule.severity() != Severity::Error),
+                    "Region check failed, nothing was written".red()
+                );
             }
-            Some(Output::Outfile(f)) => {
-                if *state {
-                    let mut file = BufWriter::new(File::create(f)?);
 
-                    writeln!(file, "R")?;
-                    for (region, string) in [Region::QM1, Region::QM2, Region::Active]
-                        .into_iter()
-                        .zip(["-q", "-o", "-a"].into_iter())
-                    {
-                        if let Ok(l) = functions::get_atomlist(pdb, region) {
-                            writeln!(
-                                file,
-                                "A {} id {}",
-                                string,
-                                l.into_iter().map(|n| n.to_string()).join(",")
-                            )?;
-                        };
+            let content = if let Some(c) = xyz {
+                Some(functions::get_xyz(pdb, c.include_qm2, c.cap)?)
+            } else if *orca {
+                Some(functions::get_orca_input(pdb, pdb_path)?)
+            } else if *state {
+                let mut state = String::from("R\n");
+                for (name, selection) in ordered_definitions(definitions) {
+                    state.push_str(&format!("define {} = {}\n", name, selection));
+                }
+                for (region, string) in [Region::QM1, Region::QM2, Region::Active]
+                    .into_iter()
+                    .zip(["-q", "-o", "-a"].into_iter())
+                {
+                    if let Ok(l) = functions::get_atomlist(pdb, region) {
+                        state.push_str(&format!(
+                            "A {} id {}\n",
+                            string,
+                            l.into_iter().map(|n| n.to_string()).join(",")
+                        ));
+                    };
+                }
+                state.push_str("W -w\n");
+                Some(state)
+            } else {
+                None
+            };
+
+            match (output, content) {
+                (None, Some(content)) => write!(io::stdout(), "{}", content)
+                    .context("Failed to print output to stdout")?,
+                (None, None) => functions::print_pdb_to_stdout(pdb)?,
+                (Some(Output::Outfile(f)), Some(content)) => {
+                    let mut file = BufWriter::new(File::create(f)?);
+                    write!(file, "{}", content)?;
+                }
+                (Some(Output::Outfile(f)), None) => {
+                    if let Err(e) = save_pdb(pdb, f, pdbtbx::StrictnessLevel::Loose) {
+                        e.into_iter()
+                            .try_for_each(|e| writeln!(io::stdout(), "{}", e))?;
+                    }
+                }
+                (Some(Output::Overwrite), _) => {
+                    if let Err(e) = save_pdb(pdb, pdb_path, pdbtbx::StrictnessLevel::Loose) {
+                        e.into_iter()
+                            .try_for_each(|e| writeln!(io::stdout(), "{}", e))?;
                     }
-                    writeln!(file, "W -w")?;
-                } else if let Err(e) = save_pdb(pdb, f, pdbtbx::StrictnessLevel::Loose) {
-                    e.into_iter()
-                        .try_for_each(|e| writeln!(io::stdout(), "{}", e))?;
                 }
             }
-            Some(Output::Overwrite) => {
-                if let Err(e) = save_pdb(pdb, pdb_path, pdbtbx::StrictnessLevel::Loose) {
+        }
+        Mode::Series {
+            centre,
+            radii,
+            active_radius,
+            prefix,
+        } => {
+            let centre = functions::get_atomlist_from_input(centre, pdb, None, definitions)?;
+            let steps = functions::get_series(pdb, &centre, radii, *active_radius)?;
+            let prefix = match prefix {
+                Some(p) => p.to_owned(),
+                None => pdb_path.trim_end_matches(".pdb").to_owned(),
+            };
+            let forcefield = format!(
+                "{}.ORCAFF.prms",
+                Path::new(pdb_path)
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or(pdb_path)
+            );
+
+            let mut files = Vec::with_capacity(steps.len());
+            for step in &steps {
+                let file = format!("{}_{}.pdb", prefix, step.radius);
+                if let Err(e) = save_pdb(&step.pdb, &file, pdbtbx::StrictnessLevel::Loose) {
                     e.into_iter()
                         .try_for_each(|e| writeln!(io::stdout(), "{}", e))?;
                 }
+                let input =
+                    functions::get_orca_input_with_forcefield(&step.pdb, &file, &forcefield)?;
+                let mut input_file =
+                    BufWriter::new(File::create(format!("{}_{}.inp", prefix, step.radius))?);
+                write!(input_file, "{}", input)?;
+                files.push(file);
+            }
+
+            writeln!(io::stdout(), "{}", functions::series_table(&steps, &files))
+                .context("Failed to write table to stdout")?;
+        }
+        Mode::Check { max_active } => {
+            let diagnostics =
+                functions::check_regions(pdb, max_active.unwrap_or(functions::MAX_ACTIVE_ATOMS));
+            if diagnostics.is_empty() {
+                writeln!(io::stdout(), "{}", "No problems found!".green())
+                    .context("Failed to write to stdout")?;
+            } else {
+                writeln!(io::stdout(), "{}", functions::check_table(&diagnostics))
+                    .context("Failed to write table to stdout")?;
+            }
+        }
+        Mode::Define { definition } => match definition {
+            Some((name, selection)) => {
+                functions::define_selection(name, selection, pdb, definitions)?
+            }
+            None => {
+                ensure!(!definitions.is_empty(), "No selections have been defined");
+
+                let mut table = Table::new();
+                table
+                    .load_preset(UTF8_FULL)
+                    .apply_modifier(UTF8_ROUND_CORNERS)
+                    .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+                table.set_header(Row::from(vec!["Name", "Selection"]));
+                for (name, selection) in definitions.iter() {
+                    table.add_row(Row::from(vec![name, selection]));
+                }
+                writeln!(io::stdout(), "{}", table).context("Failed to write table to stdout")?;
             }
         },
     }
@@ -355,7 +599,9 @@ mod tests {
         let mode = Mode::new(&matches).unwrap();
         format!(
             "{:?}",
-            dispatch(&mode, &mut pdb, pdb_path).unwrap().unwrap()
+            dispatch(&mode, &mut pdb, pdb_path, &mut Definitions::new())
+                .unwrap()
+                .unwrap()
         )
     }
 
@@ -385,13 +631,105 @@ mod tests {
         let mut pdb = test_pdb(pdb_path);
         let matches = get_matches(["Y"].into_iter());
         let mode = Mode::new(&matches).unwrap();
-        assert!(dispatch(&mode, &mut pdb, pdb_path).unwrap().is_none());
+        assert!(dispatch(&mode, &mut pdb, pdb_path, &mut Definitions::new())
+            .unwrap()
+            .is_none());
 
         // let matches = get_matches(["Q", "-rl", "12"].into_iter());
         // let mode = Mode::new(&matches).unwrap();
         // assert!(dispatch(mode, &mut pdb, pdb_path).unwrap().is_none())
     }
 
+    #[test]
+    fn define_selection() {
+        let pdb_path = "tests/test_blank.pdb";
+        let mut pdb = test_pdb(pdb_path);
+        let mut definitions = Definitions::new();
+        let mut run = |args: &[&str]| {
+            let matches = get_matches(args.iter().copied());
+            let mode = Mode::new(&matches)?;
+            dispatch(&mode, &mut pdb, pdb_path, &mut definitions)
+        };
+
+        assert!(run(&["define", "sel", "=", "resid", "1", "and", "name", "CA"]).is_ok());
+        assert!(run(&["define", "both", "=", "@sel", "or", "id", "9"]).is_ok());
+        assert!(run(&["define", "sel", "=", "@both"]).is_err());
+        assert!(run(&["define", "no", "name", "given"]).is_err());
+        assert!(run(&["define", "x-y", "=", "id", "1"]).is_err());
+        assert!(run(&["define", "bad", "=", "@missing"]).is_err());
+
+        let edit_action = format!("{:?}", run(&["A", "-q", "@both"]).unwrap().unwrap());
+        let (_, region, atoms) = get_editop(&edit_action);
+        assert_eq!(region, "QM1");
+        assert_eq!(get_atomvec(atoms), vec![4, 9]);
+        assert_eq!(
+            ordered_definitions(&definitions),
+            vec![("sel", "resid 1 and name CA"), ("both", "@sel or id 9")]
+        );
+    }
+
+    #[test]
+    fn check_blocks_write() {
+        let pdb_path = "tests/test_get_atomlist.pdb";
+        let mut pdb = test_pdb(pdb_path);
+        let write = Mode::Write {
+            output: None,
+            state: false,
+            orca: true,
+            xyz: None,
+            check: true,
+        };
+        let mut run = |mode: &Mode| dispatch(mode, &mut pdb, pdb_path, &mut Definitions::new());
+
+        // QM atoms 4, 11 and 12 are not active
+        let matches = get_matches(["check"].into_iter());
+        assert!(run(&Mode::new(&matches).unwrap()).unwrap().is_none());
+        assert!(run(&write).is_err());
+        let matches = get_matches(["A", "-a", "id", "4,11,12"].into_iter());
+        assert!(run(&Mode::new(&matches).unwrap()).is_ok());
+        assert!(run(&write).is_ok());
+    }
+
+    #[test]
+    fn replace_active_around_qm() {
+        let pdb_path = "tests/test_get_atomlist.pdb";
+        let mut pdb = test_pdb(pdb_path);
+        let active = |pdb: &PDB| functions::get_atomlist(pdb, Region::Active).unwrap();
+        let previous = active(&pdb);
+        assert_eq!(previous, vec![1, 2, 3, 5, 6, 8, 9]);
+
+        let matches = get_matches(
+            ["A", "-a", "--around-qm", "3", "--max-atoms", "10"]
+                .iter()
+                .copied(),
+        );
+        let mode = Mode::new(&matches).unwrap();
+        let edit_op = dispatch(&mode, &mut pdb, pdb_path, &mut Definitions::new())
+            .unwrap()
+            .unwrap();
+
+        // The previously active atom 3 is dropped to stay within the limit, the other ones
+        // are removed and added again
+        let replaced = vec![1, 2, 4, 5, 6, 8, 9, 11, 12];
+        assert!(active(&pdb).len() <= 10);
+        assert_eq!(active(&pdb), replaced);
+
+        edit_op.undo(&mut pdb);
+        assert_eq!(active(&pdb), previous);
+        edit_op.redo(&mut pdb);
+        assert_eq!(active(&pdb), replaced);
+
+        // Building the same region again replaces all atoms by themselves
+        let edit_op = dispatch(&mode, &mut pdb, pdb_path, &mut Definitions::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(active(&pdb), replaced);
+        edit_op.undo(&mut pdb);
+        assert_eq!(active(&pdb), replaced);
+        edit_op.redo(&mut pdb);
+        assert_eq!(active(&pdb), replaced);
+    }
+
     #[test]
     fn add_qm1() {
         let edit_action = get_edit_action(
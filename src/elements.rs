@@ -0,0 +1,51 @@
+// Element symbols ordered by atomic number.
+
+const ELEMENTS: &[&str] = &[
+    "H", "HE", "LI", "BE", "B", "C", "N", "O", "F", "NE", "NA", "MG", "AL", "SI", "P", "S", "CL",
+    "AR", "K", "CA", "SC", "TI", "V", "CR", "MN", "FE", "CO", "NI", "CU", "ZN", "GA", "GE", "AS",
+    "SE", "BR", "KR", "RB", "SR", "Y", "ZR", "NB", "MO", "TC", "RU", "RH", "PD", "AG", "CD", "IN",
+    "SN", "SB", "TE", "I", "XE", "CS", "BA", "LA", "CE", "PR", "ND", "PM", "SM", "EU", "GD", "TB",
+    "DY", "HO", "ER", "TM", "YB", "LU", "HF", "TA", "W", "RE", "OS", "IR", "PT", "AU", "HG", "TL",
+    "PB", "BI", "PO", "AT", "RN",
+];
+
+/// Returns the atomic number of the given element symbol. Deuterium is treated as hydrogen.
+pub fn atomic_number(element: &str) -> Option<usize> {
+    let element = element.trim().to_uppercase();
+    if element == "D" {
+        return Some(1);
+    }
+    ELEMENTS.iter().position(|e| *e == element).map(|i| i + 1)
+}
+
+/// Returns the element symbol of the given atomic number.
+pub fn element_symbol(atomic_number: usize) -> Option<&'static str> {
+    ELEMENTS.get(atomic_number.checked_sub(1)?).copied()
+}
+
+/// Checks whether the given element symbol belongs to a metal, excluding metalloids.
+pub fn is_metal(element: &str) -> bool {
+    matches!(
+        atomic_number(element),
+        Some(3 | 4 | 11 | 12 | 13 | 19..=31 | 37..=50 | 55..=83)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atomic_number_test() {
+        assert_eq!(atomic_number("H"), Some(1));
+        assert_eq!(atomic_number("Cu"), Some(29));
+        assert_eq!(atomic_number("ZN"), Some(30));
+        assert_eq!(atomic_number("Xx"), None);
+        assert_eq!(element_symbol(29), Some("CU"));
+        assert_eq!(element_symbol(0), None);
+        assert!(is_metal("Cu"));
+        assert!(is_metal("NA"));
+        assert!(!is_metal("C"));
+        assert!(!is_metal("Se"));
+    }
+}
@@ -1,10 +1,395 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{
+    assign_topology, forcefield_bond_graph, forcefield_charges, get_secondary_structure, in_region,
+    BondGraph, PEPTIDE_BOND_DISTANCE,
+};
+use crate::elements::{atomic_number, element_symbol};
 use crate::options::{Distance, Region, Target};
+use crate::prms::ForceField;
+use crate::residue_names::{is_ion, is_nucleic, is_protein, PROTEIN_RESIDUES};
+use crate::rtf::Topology;
 
 use anyhow::Result;
+use colored::Colorize;
 use comfy_table::modifiers::{UTF8_ROUND_CORNERS, UTF8_SOLID_INNER_BORDERS};
 use comfy_table::presets::{UTF8_BORDERS_ONLY, UTF8_FULL};
 use comfy_table::{Row, Table};
-use pdbtbx::{ContainsAtomConformer, ContainsAtomConformerResidue, PDB};
+use itertools::Itertools;
+use pdbtbx::{
+    Atom, AtomConformerResidueChainModel, ContainsAtomConformer, ContainsAtomConformerResidue,
+    Residue, PDB,
+};
+use strum_macros::Display;
+
+/// Layer of the QM/MM partitioning an atom belongs to.
+#[derive(Display, PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Ord, Hash)]
+pub enum Layer {
+    QM1,
+    QM2,
+    MM,
+}
+
+impl Layer {
+    pub fn of(atom: &Atom) -> Layer {
+        if in_region(atom, Region::QM1) {
+            Layer::QM1
+        } else if in_region(atom, Region::QM2) {
+            Layer::QM2
+        } else {
+            Layer::MM
+        }
+    }
+}
+
+/// Reasons why cutting a bond at a layer boundary is chemically questionable.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum CutWarning {
+    PeptideBond,
+    AromaticRing,
+    PolarBond,
+    MultipleCuts,
+    ChargedOuterAtom,
+}
+
+impl fmt::Display for CutWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                CutWarning::PeptideBond => "Cuts peptide bond",
+                CutWarning::AromaticRing => "Cuts aromatic ring",
+                CutWarning::PolarBond => "Cuts polar bond",
+                CutWarning::MultipleCuts => "Atom with multiple cuts",
+                CutWarning::ChargedOuterAtom => "Outer atom with large charge",
+            }
+        )
+    }
+}
+
+/// Covalent bond between two atoms belonging to different layers.
+#[derive(Debug, Clone)]
+pub struct BoundaryBond {
+    pub atoms: (usize, usize),
+    pub layers: (Layer, Layer),
+    pub warnings: Vec<CutWarning>,
+}
+
+/// Names of the ring atoms of aromatic amino acids.
+fn is_aromatic_ring_atom(residue: &str, atom: &str) -> bool {
+    let ring_atoms: &[&str] = match residue.to_uppercase().as_str() {
+        "PHE" | "TYR" => &["CG", "CD1", "CD2", "CE1", "CE2", "CZ"],
+        "TRP" => &["CG", "CD1", "CD2", "NE1", "CE2", "CE3", "CZ2", "CZ3", "CH2"],
+        "HIS" | "HIE" | "HID" | "HIP" | "HSD" | "HSE" | "HSP" => {
+            &["CG", "ND1", "CD2", "CE1", "NE2"]
+        }
+        _ => &[],
+    };
+    ring_atoms.contains(&atom.to_uppercase().as_str())
+}
+
+/// Finds all covalent bonds between atoms of different layers (QM1, QM2 and MM) and
+/// checks whether the cuts are chemically reasonable.
+pub fn find_boundary_bonds(pdb: &PDB, graph: &BondGraph) -> Vec<BoundaryBond> {
+    let atoms: HashMap<usize, AtomConformerResidueChainModel> = pdb
+        .atoms_with_hierarchy()
+        .map(|a| (a.atom().serial_number(), a))
+        .collect();
+
+    let mut boundary_bonds: Vec<BoundaryBond> = graph
+        .bonds()
+        .into_iter()
+        .filter_map(|(i, j)| {
+            let (a, b) = (atoms.get(&i)?, atoms.get(&j)?);
+            let layers = (Layer::of(a.atom()), Layer::of(b.atom()));
+            if layers.0 == layers.1 {
+                return None;
+            }
+
+            let mut warnings = Vec::new();
+            let same_residue = a.residue() == b.residue();
+            let names = (a.atom().name(), b.atom().name());
+            if !same_residue && matches!(names, ("C", "N") | ("N", "C")) {
+                warnings.push(CutWarning::PeptideBond)
+            }
+            if same_residue
+                && is_aromatic_ring_atom(a.residue().name().unwrap_or(""), names.0)
+                && is_aromatic_ring_atom(b.residue().name().unwrap_or(""), names.1)
+            {
+                warnings.push(CutWarning::AromaticRing)
+            }
+            let is_nonpolar = |e: &str| e.eq_ignore_ascii_case("C") || e.eq_ignore_ascii_case("H");
+            if !(is_nonpolar(a.atom().element()) && is_nonpolar(b.atom().element())) {
+                warnings.push(CutWarning::PolarBond)
+            }
+
+            Some(BoundaryBond {
+                atoms: (i, j),
+                layers,
+                warnings,
+            })
+        })
+        .collect();
+
+    let cut_counts = boundary_bonds
+        .iter()
+        .flat_map(|b| [b.atoms.0, b.atoms.1])
+        .counts();
+    for bond in boundary_bonds.iter_mut() {
+        if cut_counts[&bond.atoms.0] > 1 || cut_counts[&bond.atoms.1] > 1 {
+            bond.warnings.push(CutWarning::MultipleCuts)
+        }
+    }
+
+    boundary_bonds
+}
+
+/// Partial charge above which the charge of the outer atom of a cut bond is considered too
+/// large to be shifted onto its neighbors.
+const MAX_OUTER_CHARGE: f64 = 0.5;
+
+/// Lists all covalent bonds crossing the boundaries between QM1, QM2 and MM layers
+/// together with warnings for chemically questionable cuts. If a force field is given,
+/// its bonds are used and the charges of the outer atoms are checked as well.
+pub fn analyze_boundary(
+    pdb: &PDB,
+    forcefield: Option<&ForceField>,
+) -> Result<Table, anyhow::Error> {
+    let mut boundary_bonds = match forcefield {
+        Some(f) => find_boundary_bonds(pdb, &forcefield_bond_graph(pdb, f)?),
+        None => find_boundary_bonds(pdb, &BondGraph::new(pdb)),
+    };
+    ensure!(
+        !boundary_bonds.is_empty(),
+        "No bonds crossing the QM/MM boundaries found!"
+    );
+
+    if let Some(f) = forcefield {
+        let charges = forcefield_charges(pdb, f)?;
+        for bond in boundary_bonds.iter_mut() {
+            // The atom of the outer layer is replaced by a link atom
+            let outer = if bond.layers.0 > bond.layers.1 {
+                bond.atoms.0
+            } else {
+                bond.atoms.1
+            };
+            if charges[&outer].abs() > MAX_OUTER_CHARGE {
+                bond.warnings.push(CutWarning::ChargedOuterAtom)
+            }
+        }
+    }
+
+    let atoms: HashMap<usize, AtomConformerResidueChainModel> = pdb
+        .atoms_with_hierarchy()
+        .map(|a| (a.atom().serial_number(), a))
+        .collect();
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+    table.set_header(Row::from(vec![
+        "Atom ID 1",
+        "Atom Name 1",
+        "Residue 1",
+        "Layer 1",
+        "Atom ID 2",
+        "Atom Name 2",
+        "Residue 2",
+        "Layer 2",
+        "Bond",
+        "Warnings",
+    ]));
+
+    for bond in boundary_bonds {
+        let (a, b) = (&atoms[&bond.atoms.0], &atoms[&bond.atoms.1]);
+        let residue = |atom_hier: &AtomConformerResidueChainModel| {
+            format!(
+                "{} {}{}",
+                atom_hier.residue().name().unwrap_or(""),
+                atom_hier.residue().serial_number(),
+                atom_hier.residue().insertion_code().unwrap_or("")
+            )
+        };
+
+        table.add_row(Row::from(vec![
+            a.atom().serial_number().to_string(),
+            a.atom().name().to_owned(),
+            residue(a),
+            bond.layers.0.to_string(),
+            b.atom().serial_number().to_string(),
+            b.atom().name().to_owned(),
+            residue(b),
+            bond.layers.1.to_string(),
+            format!("{}-{}", a.atom().element(), b.atom().element()),
+            bond.warnings.iter().join(", ").yellow().to_string(),
+        ]));
+    }
+
+    Ok(table)
+}
+
+/// Compares all residues with their templates of the given topology and lists those that
+/// deviate, i.e. have no template, missing or extra atoms, atoms of the wrong element or
+/// template bonds not present in the structure.
+pub fn analyze_topology(pdb: &PDB, topology: &Topology) -> Result<Table, anyhow::Error> {
+    let assignment = assign_topology(pdb, topology);
+    ensure!(
+        assignment.residues.iter().any(|r| !r.is_complete()),
+        "All residues match their topology templates!"
+    );
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+    table.set_header(Row::from(vec![
+        "Chain",
+        "Residue ID",
+        "Residue Name",
+        "Template",
+        "Missing Atoms",
+        "Extra Atoms",
+        "Wrong Element",
+        "Missing Bonds",
+    ]));
+
+    for residue in assignment.residues.iter().filter(|r| !r.is_complete()) {
+        table.add_row(Row::from(vec![
+            residue.chain.clone(),
+            residue.residue.clone(),
+            residue.name.clone(),
+            match &residue.template {
+                Some(t) => t.clone(),
+                None => "None".red().to_string(),
+            },
+            residue.missing.join(", ").yellow().to_string(),
+            residue.extra.join(", ").yellow().to_string(),
+            residue.wrong_element.join(", ").red().to_string(),
+            residue
+                .unbonded
+                .iter()
+                .map(|(a, b)| format!("{}-{}", a, b))
+                .join(", ")
+                .yellow()
+                .to_string(),
+        ]));
+    }
+
+    Ok(table)
+}
+
+/// Checks whether a force field matches the PDB file: Number and order of atoms (by their
+/// elements) have to agree and the bonds should match the ones inferred from distances.
+/// Returns a summary and, if present, a table of atoms with differing elements.
+pub fn analyze_forcefield(
+    pdb: &PDB,
+    forcefield: &ForceField,
+) -> Result<(Table, Option<Table>), anyhow::Error> {
+    let status = |ok: bool| {
+        if ok {
+            "OK".green().to_string()
+        } else {
+            "Mismatch".red().to_string()
+        }
+    };
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+    table.set_header(Row::from(vec!["", "PDB File", "Force Field", "Status"]));
+
+    let atom_count_matches = pdb.atom_count() == forcefield.atoms.len();
+    table.add_row(Row::from(vec![
+        "# of Atoms".to_owned(),
+        pdb.atom_count().to_string(),
+        forcefield.atoms.len().to_string(),
+        status(atom_count_matches),
+    ]));
+
+    let mut mismatch_table = Table::new();
+    mismatch_table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+    mismatch_table.set_header(Row::from(vec![
+        "Atom ID",
+        "Atom Name",
+        "Residue ID",
+        "Residue Name",
+        "PDB Element",
+        "Force Field Element",
+    ]));
+
+    let mut mismatches = 0;
+    for (atom_hier, ff_atom) in pdb.atoms_with_hierarchy().zip(&forcefield.atoms) {
+        if atomic_number(atom_hier.atom().element()) != Some(ff_atom.atomic_number) {
+            mismatches += 1;
+            mismatch_table.add_row(Row::from(vec![
+                atom_hier.atom().serial_number().to_string(),
+                atom_hier.atom().name().to_owned(),
+                atom_hier.residue().serial_number().to_string()
+                    + atom_hier.residue().insertion_code().unwrap_or(""),
+                atom_hier.residue().name().unwrap_or("").to_owned(),
+                atom_hier.atom().element().to_owned(),
+                element_symbol(ff_atom.atomic_number)
+                    .unwrap_or("?")
+                    .to_owned(),
+            ]));
+        }
+    }
+    table.add_row(Row::from(vec![
+        "Element Order".to_owned(),
+        String::new(),
+        format!("{} mismatches", mismatches),
+        status(mismatches == 0),
+    ]));
+
+    if atom_count_matches {
+        let bonds = BondGraph::new(pdb).bonds();
+        let ff_bonds = forcefield_bond_graph(pdb, forcefield)?.bonds();
+        let common = ff_bonds.iter().filter(|b| bonds.contains(b)).count();
+        table.add_row(Row::from(vec![
+            "# of Bonds".to_owned(),
+            bonds.len().to_string(),
+            ff_bonds.len().to_string(),
+            if common == bonds.len() && common == ff_bonds.len() {
+                status(true)
+            } else {
+                format!("{} differ", bonds.len() + ff_bonds.len() - 2 * common)
+                    .yellow()
+                    .to_string()
+            },
+        ]));
+    }
+
+    let total_charge: f64 = forcefield.atoms.iter().map(|a| a.charge).sum();
+    table.add_row(Row::from(vec![
+        "Total Charge".to_owned(),
+        String::new(),
+        format!("{:+.3}", total_charge),
+        status((total_charge - total_charge.round()).abs() < 1e-3),
+    ]));
+    table.add_row(Row::from(vec![
+        "Atoms without LJ Parameters".to_owned(),
+        String::new(),
+        forcefield
+            .atoms
+            .iter()
+            .filter(|a| a.epsilon == 0.0 || a.rmin_half == 0.0)
+            .count()
+            .to_string(),
+        String::new(),
+    ]));
+
+    Ok((table, (mismatches > 0).then_some(mismatch_table)))
+}
 
 /// Finds and prints all contacts present in the PDB file structure. Definition of
 /// 'contact' is given by the 'level' arg which is 1.0A for Clashes and depends
@@ -185,6 +570,7 @@ pub fn analyze(
         residue_table.set_header(Row::from(vec![
             "Residue ID",
             "Residue Name",
+            "Secondary Structure",
             "# of Atoms",
             match region {
                 Some(Region::QM1) => "# of QM1 Atoms",
@@ -194,6 +580,7 @@ pub fn analyze(
             },
         ]));
 
+        let structure = get_secondary_structure(pdb);
         for residue in residue_list {
             let mut resid_atoms: u32 = 0;
             let mut atom_counter: u32 = 0;
@@ -210,6 +597,11 @@ pub fn analyze(
             residue_table.add_row(Row::from(vec![
                 residue.serial_number().to_string() + residue.insertion_code().unwrap_or(""),
                 residue.name().unwrap_or("").to_owned(),
+                residue
+                    .atoms()
+                    .next()
+                    .and_then(|a| structure.get(&a.serial_number()))
+                    .map_or("-".to_owned(), |s| s.to_string()),
                 atom_counter.to_string(),
                 resid_atoms.to_string(),
             ]));
@@ -259,6 +651,361 @@ pub fn analyze(
     Ok((basic_table, detailed_table))
 }
 
+/// Formal charge of a group of atoms, assigned to the atom carrying it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormalCharge {
+    pub atom: usize,
+    pub charge: isize,
+    pub group: &'static str,
+}
+
+/// Charged sidechains of amino acids given by the atom carrying the charge.
+fn sidechain_charge(residue: &str) -> Option<(&'static str, isize)> {
+    match residue {
+        "ASP" => Some(("CG", -1)),
+        "GLU" => Some(("CD", -1)),
+        "LYS" => Some(("NZ", 1)),
+        "ARG" => Some(("CZ", 1)),
+        "HIP" | "HSP" => Some(("CE1", 1)),
+        "CYM" => Some(("SG", -1)),
+        _ => None,
+    }
+}
+
+/// Strips the prefix of Amber terminal residues, e.g. 'NALA' or 'CGLU'. Only an 'N' or 'C'
+/// followed by a known three-letter amino acid is stripped. The name has to be uppercase.
+pub fn protein_base_name(name: &str) -> &str {
+    match name.strip_prefix(['N', 'C']) {
+        Some(base) if base.len() == 3 && PROTEIN_RESIDUES.contains(&base) => base,
+        _ => name,
+    }
+}
+
+/// Charges of monoatomic ions by residue name. Following the PDB convention, 'CU' and 'FE'
+/// are assumed to be Cu(II) and Fe(III).
+fn ion_charge(residue: &str) -> isize {
+    match residue {
+        "LI" | "NA" | "K" | "RB" | "CS" | "CU1" | "LI+" | "NA+" | "K+" | "RB+" | "CS+" | "LIT"
+        | "SOD" | "POT" | "RUB" | "CES" => 1,
+        "MG" | "CA" | "SR" | "BA" | "MN" | "FE2" | "CO" | "NI" | "CU" | "ZN" | "CD" | "HG"
+        | "MG2" | "CA2" | "ZN2" | "CU2" | "CAL" | "BAR" => 2,
+        "FE" | "FE3" => 3,
+        "F" | "CL" | "BR" | "IOD" | "F-" | "CL-" | "BR-" | "I-" | "CLA" => -1,
+        _ => 0,
+    }
+}
+
+/// Checks whether the carbon of the first residue forms a peptide bond with the nitrogen of
+/// the second one.
+fn peptide_bonded(before: Option<&Residue>, after: Option<&Residue>) -> bool {
+    let find = |residue: Option<&Residue>, name: &str| {
+        residue.and_then(|r| r.atoms().find(|a| a.name() == name).cloned())
+    };
+    matches!(
+        (find(before, "C"), find(after, "N")),
+        (Some(c), Some(n)) if c.distance(&n) <= PEPTIDE_BOND_DISTANCE
+    )
+}
+
+/// Returns for every residue of a chain whether it is a charged N- and C-terminus. Amino acids
+/// are termini if their Amber name says so (e.g. 'NALA' or 'CGLU'), if they carry the third amine
+/// hydrogen or the second carboxylate oxygen (Amber and CHARMM naming) or if they are the first or
+/// last amino acid of the chain and not bonded to a neighbouring residue, e.g. a cap.
+pub fn chain_termini(residues: &[&Residue]) -> Vec<(bool, bool)> {
+    let amino_acid = |r: &&Residue| is_protein(r.name().unwrap_or(""));
+    let first = residues.iter().position(amino_acid);
+    let last = residues.iter().rposition(amino_acid);
+
+    residues
+        .iter()
+        .enumerate()
+        .map(|(i, residue)| {
+            if !amino_acid(residue) {
+                return (false, false);
+            }
+            let name = residue.name().unwrap_or("").to_uppercase();
+            let prefixed = |p: char| name.starts_with(p) && protein_base_name(&name) != name;
+            let has_atom = |names: &[&str]| residue.atoms().any(|a| names.contains(&a.name()));
+            let previous = i.checked_sub(1).map(|j| residues[j]);
+            let next = residues.get(i + 1).copied();
+            (
+                prefixed('N')
+                    || has_atom(&["H3", "HT3"])
+                    || (first == Some(i) && !peptide_bonded(previous, Some(residue))),
+                prefixed('C')
+                    || has_atom(&["OXT", "OT2"])
+                    || (last == Some(i) && !peptide_bonded(Some(residue), next)),
+            )
+        })
+        .collect()
+}
+
+/// Assigns formal charges based on residue naming conventions: charged amino acid sidechains
+/// (e.g. ASP, LYS, HIP or CYM), charged termini, phosphates of nucleic acids and ions. For all
+/// other residues, the formal charges given in the PDB file are used.
+pub fn formal_charges(pdb: &PDB) -> Vec<FormalCharge> {
+    let mut charges = Vec::new();
+
+    for chain in pdb.chains() {
+        let residues: Vec<&Residue> = chain.residues().collect();
+        for (residue, termini) in residues.iter().zip(chain_termini(&residues)) {
+            formal_residue_charges(residue, termini, &mut charges);
+        }
+    }
+
+    charges
+}
+
+/// Adds the formal charges of a residue, see `formal_charges`. The termini are given as
+/// returned by `chain_termini`.
+fn formal_residue_charges(
+    residue: &Residue,
+    (n_terminus, c_terminus): (bool, bool),
+    charges: &mut Vec<FormalCharge>,
+) {
+    let name = residue.name().unwrap_or("").to_uppercase();
+    let find = |atom_name: &str| residue.atoms().find(|a| a.name() == atom_name);
+
+    if is_protein(&name) {
+        if let Some((atom_name, charge)) = sidechain_charge(protein_base_name(&name)) {
+            if let Some(atom) = find(atom_name) {
+                charges.push(FormalCharge {
+                    atom: atom.serial_number(),
+                    charge,
+                    group: "Sidechain",
+                })
+            }
+        }
+        if let Some(n) = find("N").filter(|_| n_terminus) {
+            charges.push(FormalCharge {
+                atom: n.serial_number(),
+                charge: 1,
+                group: "N-terminus",
+            })
+        }
+        if let Some(c) = find("C").filter(|_| c_terminus) {
+            charges.push(FormalCharge {
+                atom: c.serial_number(),
+                charge: -1,
+                group: "C-terminus",
+            })
+        }
+    } else if is_nucleic(&name) {
+        if let Some(p) = find("P") {
+            charges.push(FormalCharge {
+                atom: p.serial_number(),
+                charge: -1,
+                group: "Phosphate",
+            })
+        }
+    } else if is_ion(&name) && residue.atom_count() == 1 {
+        let atom = residue.atoms().next().unwrap();
+        let charge = if atom.charge() != 0 {
+            atom.charge()
+        } else {
+            ion_charge(&name)
+        };
+        if charge != 0 {
+            charges.push(FormalCharge {
+                atom: atom.serial_number(),
+                charge,
+                group: "Ion",
+            })
+        }
+    } else {
+        charges.extend(
+            residue
+                .atoms()
+                .filter(|a| a.charge() != 0)
+                .map(|a| FormalCharge {
+                    atom: a.serial_number(),
+                    charge: a.charge(),
+                    group: "Atom",
+                }),
+        );
+    }
+}
+
+/// Charge and electron count of a QM region.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionCharge {
+    pub charge: isize,
+    pub link_atoms: usize,
+    pub electrons: usize,
+}
+
+impl RegionCharge {
+    /// Checks whether the number of electrons is compatible with the given multiplicity.
+    pub fn matches_multiplicity(&self, multiplicity: usize) -> bool {
+        let unpaired = multiplicity.saturating_sub(1);
+        multiplicity > 0 && self.electrons >= unpaired && self.electrons % 2 == unpaired % 2
+    }
+
+    /// Returns the lowest multiplicity compatible with the number of electrons.
+    pub fn lowest_multiplicity(&self) -> usize {
+        self.electrons % 2 + 1
+    }
+}
+
+/// Estimates charge and number of electrons of the given layers, including the hydrogen
+/// link atoms ORCA places at every bond to atoms of outer layers.
+pub fn get_region_charge(
+    pdb: &PDB,
+    charges: &[FormalCharge],
+    graph: &BondGraph,
+    layers: &[Layer],
+) -> Result<RegionCharge, anyhow::Error> {
+    let in_region: HashMap<usize, bool> = pdb
+        .atoms()
+        .map(|a| (a.serial_number(), layers.contains(&Layer::of(a))))
+        .collect();
+
+    let charge: isize = charges
+        .iter()
+        .filter(|c| in_region[&c.atom])
+        .map(|c| c.charge)
+        .sum();
+    let link_atoms = graph
+        .bonds()
+        .into_iter()
+        .filter(|(i, j)| in_region[i] != in_region[j])
+        .count();
+
+    let mut nuclear_charge = 0;
+    for atom in pdb.atoms().filter(|a| in_region[&a.serial_number()]) {
+        nuclear_charge += atomic_number(atom.element()).ok_or_else(|| {
+            anyhow!(
+                "No atomic number found for element '{}' of atom {}",
+                atom.element(),
+                atom.serial_number()
+            )
+        })?;
+    }
+
+    let electrons = (nuclear_charge + link_atoms) as isize - charge;
+    ensure!(electrons >= 0, "Negative number of electrons in QM region");
+
+    Ok(RegionCharge {
+        charge,
+        link_atoms,
+        electrons: electrons as usize,
+    })
+}
+
+/// Sums up the partial charges of all atoms in the given layers. Returns None if any of
+/// these atoms has no partial charge.
+pub fn partial_charge_sum(
+    pdb: &PDB,
+    partial_charges: &HashMap<usize, f64>,
+    layers: &[Layer],
+) -> Option<f64> {
+    pdb.atoms()
+        .filter(|a| layers.contains(&Layer::of(a)))
+        .map(|a| partial_charges.get(&a.serial_number()))
+        .sum()
+}
+
+/// Estimates the formal charges of QM1 and QM1+QM2 together with their numbers of electrons
+/// and checks whether these are compatible with the requested multiplicity. The partial
+/// charges of the force field, if given, or else of the topology are summed up as well for
+/// comparison.
+pub fn analyze_charge(
+    pdb: &PDB,
+    multiplicity: usize,
+    topology: &Topology,
+    forcefield: Option<&ForceField>,
+) -> Result<(Table, Table), anyhow::Error> {
+    let charges = formal_charges(pdb);
+    let (partial_charges, graph) = match forcefield {
+        Some(f) => (forcefield_charges(pdb, f)?, forcefield_bond_graph(pdb, f)?),
+        None => (assign_topology(pdb, topology).charges, BondGraph::new(pdb)),
+    };
+    let layer_of: HashMap<usize, Layer> = pdb
+        .atoms()
+        .map(|a| (a.serial_number(), Layer::of(a)))
+        .collect();
+    ensure!(
+        layer_of.values().any(|l| *l == Layer::QM1),
+        "No atoms in QM1 region!"
+    );
+
+    let mut group_table = Table::new();
+    group_table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+    group_table.set_header(Row::from(vec![
+        "Atom ID",
+        "Atom Name",
+        "Residue ID",
+        "Residue Name",
+        "Group",
+        "Charge",
+        "Layer",
+    ]));
+
+    let atoms: HashMap<usize, AtomConformerResidueChainModel> = pdb
+        .atoms_with_hierarchy()
+        .map(|a| (a.atom().serial_number(), a))
+        .collect();
+    for c in charges.iter().filter(|c| layer_of[&c.atom] != Layer::MM) {
+        let atom_hier = &atoms[&c.atom];
+        group_table.add_row(Row::from(vec![
+            c.atom.to_string(),
+            atom_hier.atom().name().to_owned(),
+            atom_hier.residue().serial_number().to_string()
+                + atom_hier.residue().insertion_code().unwrap_or(""),
+            atom_hier.residue().name().unwrap_or("").to_owned(),
+            c.group.to_owned(),
+            format!("{:+}", c.charge),
+            layer_of[&c.atom].to_string(),
+        ]));
+    }
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+    table.set_header(Row::from(vec![
+        "",
+        "Charge",
+        "Partial Charges",
+        "# of Link Atoms",
+        "# of Electrons",
+        "Multiplicity",
+        "Parity",
+    ]));
+
+    for (label, layers) in [
+        ("QM1", vec![Layer::QM1]),
+        ("QM1+QM2", vec![Layer::QM1, Layer::QM2]),
+    ] {
+        let region_charge = get_region_charge(pdb, &charges, &graph, &layers)?;
+        let parity = if region_charge.matches_multiplicity(multiplicity) {
+            "OK".green().to_string()
+        } else {
+            format!("Mismatch, try {}", region_charge.lowest_multiplicity())
+                .red()
+                .to_string()
+        };
+
+        table.add_row(Row::from(vec![
+            label.to_owned(),
+            format!("{:+}", region_charge.charge),
+            partial_charge_sum(pdb, &partial_charges, &layers)
+                .map(|c| format!("{:+.3}", c))
+                .unwrap_or_else(|| "-".to_owned()),
+            region_charge.link_atoms.to_string(),
+            region_charge.electrons.to_string(),
+            multiplicity.to_string(),
+            parity,
+        ]));
+    }
+
+    Ok((group_table, table))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -369,18 +1116,21 @@ mod tests {
         residue_table.set_header(Row::from(vec![
             "Residue ID",
             "Residue Name",
+            "Secondary Structure",
             "# of Atoms",
             "# of QM2 Atoms",
         ]));
         residue_table.add_row(Row::from(vec![
             3_u8.to_string(),
             "TYR".to_owned(),
+            "Coil".to_owned(),
             21_u8.to_string(),
             1_u8.to_string(),
         ]));
         residue_table.add_row(Row::from(vec![
             4_u8.to_string(),
             "VAL".to_owned(),
+            "Coil".to_owned(),
             16_u8.to_string(),
             1_u8.to_string(),
         ]));
@@ -390,6 +1140,136 @@ mod tests {
         );
     }
 
+    #[test]
+    fn boundary_test() {
+        let pdb = test_pdb("tests/test_get_atomlist.pdb");
+        let boundary_bonds = find_boundary_bonds(&pdb, &BondGraph::new(&pdb));
+        let find = |i: usize, j: usize| {
+            boundary_bonds
+                .iter()
+                .find(|b| b.atoms == (i, j))
+                .unwrap()
+                .clone()
+        };
+
+        // CA-C bond between QM1 and MM
+        let bond = find(4, 17);
+        assert_eq!(bond.layers, (Layer::QM1, Layer::MM));
+        assert!(bond.warnings.is_empty());
+        // N-H bond between QM1 and MM
+        assert_eq!(find(1, 3).warnings, vec![CutWarning::PolarBond]);
+        // CB-CG bond between QM1 and QM2
+        assert_eq!(find(6, 9).layers, (Layer::QM1, Layer::QM2));
+        // Cuts through the imidazole ring of QM2 atoms
+        assert!(find(9, 10).warnings.contains(&CutWarning::AromaticRing));
+        assert!(find(9, 10).warnings.contains(&CutWarning::MultipleCuts));
+        assert!(analyze_boundary(&test_pdb("tests/test_blank.pdb"), None).is_err());
+    }
+
+    #[test]
+    fn forcefield_test() {
+        let pdb = test_pdb("tests/test_bonds.pdb");
+        let forcefield = ForceField::from_file("tests/test_bonds.prms").unwrap();
+        let (_, mismatches) = analyze_forcefield(&pdb, &forcefield).unwrap();
+        assert!(mismatches.is_none());
+
+        // Swapped oxygen and hydrogen of the second water
+        let mut swapped = ForceField::from_file("tests/test_bonds.prms").unwrap();
+        swapped.atoms.swap(3, 4);
+        let (_, mismatches) = analyze_forcefield(&pdb, &swapped).unwrap();
+        assert_eq!(mismatches.unwrap().row_iter().count(), 2);
+
+        // Copper is bonded to the oxygen of the first water only in the force field
+        let mut pdb = pdb;
+        pdb.atoms_mut().for_each(|a| a.set_occupancy(1.0).unwrap());
+        pdb.atoms_mut()
+            .filter(|a| a.serial_number() == 7)
+            .for_each(|a| a.set_occupancy(0.0).unwrap());
+        let table = analyze_boundary(&pdb, Some(&forcefield)).unwrap();
+        assert!(table
+            .to_string()
+            .contains(&CutWarning::ChargedOuterAtom.to_string()));
+    }
+
+    #[test]
+    fn formal_charges_test() {
+        let pdb = test_pdb("tests/test_insert.pdb");
+        let charges = formal_charges(&pdb);
+        let count = |group: &str, charge: isize| {
+            charges
+                .iter()
+                .filter(|c| c.group == group && c.charge == charge)
+                .count()
+        };
+
+        // 2 ARG, 11 LYS and 1 HIP as well as 12 ASP and 4 GLU
+        assert_eq!(count("Sidechain", 1), 14);
+        assert_eq!(count("Sidechain", -1), 16);
+        assert_eq!(count("C-terminus", -1), 1);
+        assert_eq!(count("Ion", 2), 1);
+        assert_eq!(count("Ion", 1), 1);
+        assert_eq!(charges.iter().map(|c| c.charge).sum::<isize>(), 0);
+
+        // A single amino acid without hydrogens is charged at both termini
+        let pdb = test_pdb("tests/test_hbonds_heavy.pdb");
+        let charges = formal_charges(&pdb);
+        assert_eq!(charges.len(), 2);
+        assert_eq!((charges[0].atom, charges[0].group), (1, "N-terminus"));
+        assert_eq!((charges[1].atom, charges[1].group), (3, "C-terminus"));
+
+        // The first and last amino acid are termini although the amine carries only two
+        // hydrogens and the second carboxylate oxygen is missing
+        let pdb = test_pdb("tests/test_blank.pdb");
+        let termini: Vec<(usize, &str)> = formal_charges(&pdb)
+            .iter()
+            .filter(|c| c.group.ends_with("terminus"))
+            .map(|c| (c.atom, c.group))
+            .collect();
+        assert_eq!(termini, [(1, "N-terminus"), (76, "C-terminus")]);
+
+        assert_eq!(protein_base_name("NALA"), "ALA");
+        assert_eq!(protein_base_name("CHIE"), "HIE");
+        assert_eq!(protein_base_name("ASPP"), "ASPP");
+        assert_eq!(protein_base_name("NMEA"), "NMEA");
+        assert_eq!(protein_base_name("CYS"), "CYS");
+    }
+
+    #[test]
+    fn region_charge_test() {
+        let pdb = test_pdb("tests/test_get_atomlist.pdb");
+        let graph = BondGraph::new(&pdb);
+        let charges = formal_charges(&pdb);
+
+        // QM1 contains the N-terminus which is charged although its third hydrogen is missing
+        let qm1 = get_region_charge(&pdb, &charges, &graph, &[Layer::QM1]).unwrap();
+        assert_eq!(qm1.charge, 1);
+        assert_eq!(qm1.link_atoms, 5);
+        assert_eq!(qm1.electrons, 25);
+
+        assert!(qm1.matches_multiplicity(2));
+        assert!(!qm1.matches_multiplicity(1));
+        assert_eq!(qm1.lowest_multiplicity(), 2);
+
+        let qm = get_region_charge(&pdb, &charges, &graph, &[Layer::QM1, Layer::QM2]).unwrap();
+        assert_eq!(qm.charge, 1);
+        assert_eq!(qm.link_atoms, 7);
+        assert_eq!(qm.electrons, 41);
+
+        // Atoms 2 and 3 (H1 and H2) are amine hydrogens of the charged N-terminus
+        let partial_charges = assign_topology(&pdb, &Topology::charmm36_protein()).charges;
+        let qm1 = partial_charge_sum(&pdb, &partial_charges, &[Layer::QM1]).unwrap();
+        assert!((qm1 - 0.26).abs() < 1e-6);
+        assert_eq!(
+            partial_charge_sum(&pdb, &HashMap::new(), &[Layer::QM1]),
+            None
+        );
+        let qm2 = partial_charge_sum(&pdb, &partial_charges, &[Layer::QM2]).unwrap();
+        assert!((qm2 - 0.69).abs() < 1e-6);
+
+        let topology = Topology::charmm36_protein();
+        assert!(analyze_charge(&test_pdb("tests/test_blank.pdb"), 1, &topology, None).is_err());
+    }
+
     // #[test]
     // fn contacts_test() {
     //     let pdb = test_pdb("tests/test_clash.pdb");
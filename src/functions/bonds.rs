@@ -0,0 +1,174 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use pdbtbx::PDB;
+
+/// Tolerance in A added to the sum of covalent radii when inferring bonds from distances.
+const BOND_TOLERANCE: f64 = 0.45;
+
+/// Covalent radii in A (Cordero et al., Dalton Trans. 2008) of the elements for which bonds are
+/// inferred from distances. Bonds to all other elements, most notably metals, are only taken
+/// from CONECT records since coordination bonds cannot be told apart by distance alone.
+fn covalent_radius(element: &str) -> Option<f64> {
+    let radius = match element.to_uppercase().as_str() {
+        "H" | "D" => 0.31,
+        "B" => 0.84,
+        "C" => 0.76,
+        "N" => 0.71,
+        "O" => 0.66,
+        "F" => 0.57,
+        "SI" => 1.11,
+        "P" => 1.07,
+        "S" => 1.05,
+        "CL" => 1.02,
+        "SE" => 1.20,
+        "BR" => 1.20,
+        "I" => 1.39,
+        _ => return None,
+    };
+    Some(radius)
+}
+
+/// Connectivity of all atoms of a PDB structure given by atom IDs. Bonds are read from
+/// CONECT records and inferred from interatomic distances and covalent radii.
+#[derive(Debug, Default)]
+pub struct BondGraph {
+    neighbors: HashMap<usize, Vec<usize>>,
+}
+
+impl BondGraph {
+    pub fn new(pdb: &PDB) -> BondGraph {
+        let mut graph = BondGraph::default();
+
+        for (a, b, _) in pdb.bonds() {
+            graph.add_bond(a.serial_number(), b.serial_number());
+        }
+
+        let tree = pdb.create_atom_rtree();
+        let max_distance = 2.0 * 1.39 + BOND_TOLERANCE;
+
+        for atom in pdb.atoms() {
+            let radius = match covalent_radius(atom.element()) {
+                Some(r) => r,
+                None => continue,
+            };
+
+            for other in tree.locate_within_distance(atom.pos(), max_distance.powi(2)) {
+                if other.serial_number() <= atom.serial_number() {
+                    continue;
+                }
+                if let Some(other_radius) = covalent_radius(other.element()) {
+                    if atom.distance(other) <= radius + other_radius + BOND_TOLERANCE {
+                        graph.add_bond(atom.serial_number(), other.serial_number());
+                    }
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// Creates a graph from bonds given by pairs of atom IDs, e.g. from a force field.
+    pub fn from_bonds(bonds: impl IntoIterator<Item = (usize, usize)>) -> BondGraph {
+        let mut graph = BondGraph::default();
+        for (a, b) in bonds {
+            graph.add_bond(a, b)
+        }
+        graph
+    }
+
+    fn add_bond(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
+        }
+        for (i, j) in [(a, b), (b, a)] {
+            let list = self.neighbors.entry(i).or_default();
+            if let Err(pos) = list.binary_search(&j) {
+                list.insert(pos, j)
+            }
+        }
+    }
+
+    /// Returns the IDs of all atoms bonded to the given atom, sorted ascendingly.
+    pub fn neighbors(&self, atom: usize) -> &[usize] {
+        self.neighbors.get(&atom).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Returns all bonds as pairs of atom IDs with the lower ID first, sorted ascendingly.
+    pub fn bonds(&self) -> Vec<(usize, usize)> {
+        let mut bonds: Vec<(usize, usize)> = self
+            .neighbors
+            .iter()
+            .flat_map(|(&a, list)| list.iter().filter(move |&&b| a < b).map(move |&b| (a, b)))
+            .collect();
+        bonds.sort_unstable();
+        bonds
+    }
+
+    /// Returns all atoms that are bonded to any of the given atoms but not part of them.
+    pub fn bonded_to(&self, atoms: &[usize]) -> Vec<usize> {
+        let atom_set: HashSet<&usize> = atoms.iter().collect();
+        let mut bonded: Vec<usize> = atoms
+            .iter()
+            .flat_map(|a| self.neighbors(*a))
+            .filter(|a| !atom_set.contains(a))
+            .copied()
+            .collect();
+        bonded.sort_unstable();
+        bonded.dedup();
+        bonded
+    }
+
+    /// Returns all atoms that are at most 'max_bonds' bonds away from any of the given atoms,
+    /// including these.
+    pub fn within_bonds(&self, atoms: &[usize], max_bonds: usize) -> Vec<usize> {
+        let mut visited: HashSet<usize> = atoms.iter().copied().collect();
+        let mut queue: VecDeque<(usize, usize)> = atoms.iter().map(|a| (*a, 0)).collect();
+
+        while let Some((atom, depth)) = queue.pop_front() {
+            if depth == max_bonds {
+                continue;
+            }
+            for &n in self.neighbors(atom) {
+                if visited.insert(n) {
+                    queue.push_back((n, depth + 1))
+                }
+            }
+        }
+
+        let mut result: Vec<usize> = visited.into_iter().collect();
+        result.sort_unstable();
+        result
+    }
+
+    /// Returns all atoms of the molecules (connected fragments) any of the given atoms belong to.
+    pub fn fragments(&self, atoms: &[usize]) -> Vec<usize> {
+        self.within_bonds(atoms, usize::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdbtbx::StrictnessLevel;
+
+    fn test_pdb(path: &str) -> PDB {
+        let (pdb, _) = pdbtbx::open_pdb(path, StrictnessLevel::Strict).unwrap();
+        pdb
+    }
+
+    #[test]
+    fn bond_graph_test() {
+        let pdb = test_pdb("tests/test_bonds.pdb");
+        let graph = BondGraph::new(&pdb);
+
+        // Bonds of the water molecules are inferred from distances
+        assert_eq!(graph.neighbors(1), &[2, 3, 7]);
+        assert_eq!(graph.neighbors(4), &[5, 6]);
+        assert_eq!(graph.bonded_to(&[2]), vec![1]);
+        assert_eq!(graph.within_bonds(&[2], 1), vec![1, 2]);
+        // The copper ion is only bonded via CONECT records
+        assert_eq!(graph.neighbors(7), &[1]);
+        assert_eq!(graph.fragments(&[7]), vec![1, 2, 3, 7]);
+        assert_eq!(graph.fragments(&[5]), vec![4, 5, 6]);
+    }
+}
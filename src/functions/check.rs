@@ -0,0 +1,259 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use super::{format_id_ranges, in_region, BondGraph, IdNotation, Layer};
+use crate::options::Region;
+
+use colored::Colorize;
+use comfy_table::modifiers::{UTF8_ROUND_CORNERS, UTF8_SOLID_INNER_BORDERS};
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{Row, Table};
+use pdbtbx::{Atom, PDB};
+use strum_macros::Display;
+
+/// Number of active atoms above which a warning is issued if no other limit is given.
+pub const MAX_ACTIVE_ATOMS: usize = 2000;
+
+/// Distance in A within which a QM2 fragment not bonded to QM1 still counts as adjacent to it.
+const QM2_CONTACT_DISTANCE: f64 = 4.0;
+
+/// Severity of a region diagnostic. Errors prevent the structure from being written.
+#[derive(Display, PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Rules the QM and active regions are checked against.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum CheckRule {
+    QMNotActive,
+    IsolatedHydrogen,
+    QMBondedToMM,
+    DetachedQM2,
+    ActiveSize,
+}
+
+impl CheckRule {
+    pub fn severity(&self) -> Severity {
+        match self {
+            CheckRule::QMNotActive | CheckRule::IsolatedHydrogen | CheckRule::QMBondedToMM => {
+                Severity::Error
+            }
+            CheckRule::DetachedQM2 | CheckRule::ActiveSize => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for CheckRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                CheckRule::QMNotActive => "QM atoms outside active region",
+                CheckRule::IsolatedHydrogen => "QM hydrogens without their heavy atom",
+                CheckRule::QMBondedToMM => "QM atoms bonded only to MM atoms",
+                CheckRule::DetachedQM2 => "QM2 fragment not adjacent to QM1",
+                CheckRule::ActiveSize => "Active region too large",
+            }
+        )
+    }
+}
+
+/// Violation of a rule together with the atoms causing it or a description.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub rule: CheckRule,
+    pub details: String,
+}
+
+/// Returns the fragments formed by bonds between the given atoms.
+fn fragments_within(graph: &BondGraph, atoms: &[usize]) -> Vec<Vec<usize>> {
+    let atom_set: HashSet<usize> = atoms.iter().copied().collect();
+    let mut visited: HashSet<usize> = HashSet::new();
+    let mut fragments = Vec::new();
+
+    for &start in atoms {
+        if !visited.insert(start) {
+            continue;
+        }
+        let mut fragment = vec![start];
+        let mut stack = vec![start];
+        while let Some(atom) = stack.pop() {
+            for &n in graph.neighbors(atom) {
+                if atom_set.contains(&n) && visited.insert(n) {
+                    fragment.push(n);
+                    stack.push(n);
+                }
+            }
+        }
+        fragment.sort_unstable();
+        fragments.push(fragment);
+    }
+
+    fragments
+}
+
+/// Checks the QM and active regions for inconsistencies: QM atoms have to be active, QM
+/// hydrogens need their heavy atom and QM atoms may not be bonded to MM atoms only. QM2
+/// fragments should be bonded or close to QM1 and the active region should not exceed
+/// 'max_active' atoms. Diagnostics are sorted by severity.
+pub fn check_regions(pdb: &PDB, max_active: usize) -> Vec<Diagnostic> {
+    let graph = BondGraph::new(pdb);
+    let atoms: HashMap<usize, &Atom> = pdb.atoms().map(|a| (a.serial_number(), a)).collect();
+    let layer = |id: &usize| atoms.get(id).map_or(Layer::MM, |a| Layer::of(a));
+
+    let mut diagnostics = Vec::new();
+    let mut add = |rule: CheckRule, ids: Vec<usize>| {
+        if !ids.is_empty() {
+            diagnostics.push(Diagnostic {
+                rule,
+                details: format_id_ranges(&ids, IdNotation::Serial),
+            })
+        }
+    };
+
+    let qm_atoms: Vec<&Atom> = pdb.atoms().filter(|a| Layer::of(a) != Layer::MM).collect();
+    let is_hydrogen = |a: &Atom| a.element().eq_ignore_ascii_case("H");
+
+    add(
+        CheckRule::QMNotActive,
+        qm_atoms
+            .iter()
+            .filter(|a| a.b_factor() != 1.00)
+            .map(|a| a.serial_number())
+            .collect(),
+    );
+
+    // Atoms without any bonds, e.g. metal ions, are fine on their own
+    let only_mm_neighbors = |a: &Atom| {
+        let neighbors = graph.neighbors(a.serial_number());
+        !neighbors.is_empty() && neighbors.iter().all(|n| layer(n) == Layer::MM)
+    };
+    add(
+        CheckRule::IsolatedHydrogen,
+        qm_atoms
+            .iter()
+            .filter(|a| is_hydrogen(a) && only_mm_neighbors(a))
+            .map(|a| a.serial_number())
+            .collect(),
+    );
+    add(
+        CheckRule::QMBondedToMM,
+        qm_atoms
+            .iter()
+            .filter(|a| !is_hydrogen(a) && only_mm_neighbors(a))
+            .map(|a| a.serial_number())
+            .collect(),
+    );
+
+    let qm1_atoms: Vec<&Atom> = qm_atoms
+        .iter()
+        .filter(|a| Layer::of(a) == Layer::QM1)
+        .copied()
+        .collect();
+    let qm2_ids: Vec<usize> = qm_atoms
+        .iter()
+        .filter(|a| Layer::of(a) == Layer::QM2)
+        .map(|a| a.serial_number())
+        .collect();
+    for fragment in fragments_within(&graph, &qm2_ids) {
+        let bonded = graph
+            .bonded_to(&fragment)
+            .iter()
+            .any(|a| layer(a) == Layer::QM1);
+        let close = fragment.iter().any(|a| {
+            qm1_atoms
+                .iter()
+                .any(|q| atoms[a].distance(q) <= QM2_CONTACT_DISTANCE)
+        });
+        if !bonded && !close {
+            add(CheckRule::DetachedQM2, fragment)
+        }
+    }
+
+    let active_count = pdb.atoms().filter(|a| in_region(a, Region::Active)).count();
+    if active_count > max_active {
+        diagnostics.push(Diagnostic {
+            rule: CheckRule::ActiveSize,
+            details: format!("{} atoms exceed limit of {}", active_count, max_active),
+        })
+    }
+
+    diagnostics.sort_by_key(|d| d.rule.severity());
+    diagnostics
+}
+
+/// Creates a table of the given diagnostics with colored severities.
+pub fn check_table(diagnostics: &[Diagnostic]) -> Table {
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+    table.set_header(Row::from(vec!["Severity", "Rule", "Details"]));
+
+    for diagnostic in diagnostics {
+        let severity = match diagnostic.rule.severity() {
+            Severity::Error => "Error".red(),
+            Severity::Warning => "Warning".yellow(),
+        };
+        table.add_row(Row::from(vec![
+            severity.to_string(),
+            diagnostic.rule.to_string(),
+            diagnostic.details.clone(),
+        ]));
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdbtbx::StrictnessLevel;
+
+    fn test_pdb(path: &str) -> PDB {
+        let (pdb, _) = pdbtbx::open_pdb(path, StrictnessLevel::Strict).unwrap();
+        pdb
+    }
+
+    #[test]
+    fn check_regions_test() {
+        let mut pdb = test_pdb("tests/test_blank.pdb");
+        assert!(check_regions(&pdb, MAX_ACTIVE_ATOMS).is_empty());
+
+        // Histidine sidechain with an amide hydrogen and a glutamate oxygen in QM1,
+        // its backbone and a distant water in QM2. The water is not active.
+        let qm1: Vec<usize> = (6..=16).chain([20, 74]).collect();
+        let qm2 = [4, 5, 78, 79, 80];
+        for atom in pdb.atoms_mut() {
+            let id = atom.serial_number();
+            if qm1.contains(&id) {
+                atom.set_occupancy(1.00).unwrap()
+            } else if qm2.contains(&id) {
+                atom.set_occupancy(2.00).unwrap()
+            }
+            if (qm1.contains(&id) || qm2.contains(&id)) && id < 78 {
+                atom.set_b_factor(1.00).unwrap()
+            }
+        }
+
+        let diagnostic = |rule, details: &str| Diagnostic {
+            rule,
+            details: details.to_owned(),
+        };
+        assert_eq!(
+            check_regions(&pdb, 10),
+            vec![
+                diagnostic(CheckRule::QMNotActive, "78-80"),
+                diagnostic(CheckRule::IsolatedHydrogen, "20"),
+                diagnostic(CheckRule::QMBondedToMM, "74"),
+                diagnostic(CheckRule::DetachedQM2, "78-80"),
+                diagnostic(CheckRule::ActiveSize, "15 atoms exceed limit of 10"),
+            ]
+        );
+        assert_eq!(check_regions(&pdb, MAX_ACTIVE_ATOMS).len(), 4);
+    }
+}
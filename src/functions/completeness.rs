@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{assign_topology, protein_base_name, ResidueMatch, PEPTIDE_BOND_DISTANCE};
+use crate::residue_ascii::RESIDUE_ASCII;
+use crate::residue_names::is_protein;
+use crate::rtf::Topology;
+
+use comfy_table::modifiers::{UTF8_ROUND_CORNERS, UTF8_SOLID_INNER_BORDERS};
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{Row, Table};
+use pdbtbx::{Residue, PDB};
+
+/// Discontinuity between two consecutive amino acids of a chain.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum GapKind {
+    /// The residues are not bonded and their numbers skip the given number of residues.
+    MissingResidues(usize),
+    /// The residues are not bonded although their numbers are consecutive.
+    ChainBreak,
+    /// The residues are bonded although their numbers are not consecutive.
+    NumberingGap,
+}
+
+impl fmt::Display for GapKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GapKind::MissingResidues(1) => write!(f, "1 missing residue"),
+            GapKind::MissingResidues(n) => write!(f, "{} missing residues", n),
+            GapKind::ChainBreak => write!(f, "Chain break"),
+            GapKind::NumberingGap => write!(f, "Numbering gap"),
+        }
+    }
+}
+
+/// Gap between two amino acids given by residue name and number. The distance between the
+/// carbon of the first and the nitrogen of the second residue is unknown if one is missing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainGap {
+    pub chain: String,
+    pub before: String,
+    pub after: String,
+    pub distance: Option<f64>,
+    pub kind: GapKind,
+}
+
+/// Atoms of a standard amino acid missing from or not present in its template. Atom names
+/// follow the conventions of the topology.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResidueCompleteness {
+    pub chain: String,
+    pub residue: String,
+    pub missing: Vec<String>,
+    pub missing_hydrogens: Vec<String>,
+    pub unexpected: Vec<String>,
+}
+
+fn residue_label(residue: &Residue) -> String {
+    format!(
+        "{} {}{}",
+        residue.name().unwrap_or(""),
+        residue.serial_number(),
+        residue.insertion_code().unwrap_or("")
+    )
+}
+
+/// Finds gaps between consecutive amino acids of all chains. Residues are considered bonded
+/// if the peptide bond is at most 2.5 A long or, if a backbone atom is missing, if their
+/// numbers are consecutive.
+pub fn find_chain_gaps(pdb: &PDB) -> Vec<ChainGap> {
+    let mut gaps = Vec::new();
+
+    for chain in pdb.chains() {
+        let residues: Vec<&Residue> = chain
+            .residues()
+            .filter(|r| is_protein(r.name().unwrap_or("")))
+            .collect();
+
+        for pair in residues.windows(2) {
+            let (before, after) = (pair[0], pair[1]);
+            let distance = match (
+                before.atoms().find(|a| a.name() == "C"),
+                after.atoms().find(|a| a.name() == "N"),
+            ) {
+                (Some(c), Some(n)) => Some(c.distance(n)),
+                _ => None,
+            };
+            // Insertion codes continue the numbering of their residue
+            let skipped = (after.serial_number() - before.serial_number() - 1).max(0) as usize;
+            let bonded = match distance {
+                Some(d) => d <= PEPTIDE_BOND_DISTANCE,
+                None => skipped == 0,
+            };
+
+            let kind = match (bonded, skipped) {
+                (true, 0) => continue,
+                (true, _) => GapKind::NumberingGap,
+                (false, 0) => GapKind::ChainBreak,
+                (false, n) => GapKind::MissingResidues(n),
+            };
+            gaps.push(ChainGap {
+                chain: chain.id().to_owned(),
+                before: residue_label(before),
+                after: residue_label(after),
+                distance,
+                kind,
+            });
+        }
+    }
+
+    gaps
+}
+
+/// Compares all standard amino acids with their templates of the topology. Template atoms
+/// starting with 'H' are counted as hydrogens. Termini are compared with the templates of
+/// charged termini as given by `chain_termini`.
+pub fn check_completeness(pdb: &PDB, topology: &Topology) -> Vec<ResidueCompleteness> {
+    let mut residue_matches: HashMap<(String, String), ResidueMatch> =
+        assign_topology(pdb, topology)
+            .residues
+            .into_iter()
+            .map(|m| ((m.chain.clone(), m.residue.clone()), m))
+            .collect();
+    let mut checked = Vec::new();
+
+    for chain in pdb.chains() {
+        for residue in chain.residues() {
+            let key = (
+                chain.id().to_owned(),
+                residue.serial_number().to_string() + residue.insertion_code().unwrap_or(""),
+            );
+            let residue_match = match residue_matches.remove(&key) {
+                Some(m) => m,
+                None => continue,
+            };
+            let name = residue.name().unwrap_or("").to_uppercase();
+            if residue_match.template.is_none()
+                || !is_protein(&name)
+                || !RESIDUE_ASCII.contains_key(protein_base_name(&name))
+            {
+                continue;
+            }
+
+            let (missing, missing_hydrogens): (Vec<String>, Vec<String>) = residue_match
+                .missing
+                .into_iter()
+                .partition(|a| !a.starts_with('H'));
+
+            checked.push(ResidueCompleteness {
+                chain: residue_match.chain,
+                residue: residue_label(residue),
+                missing,
+                missing_hydrogens,
+                unexpected: residue_match.extra,
+            });
+        }
+    }
+
+    checked
+}
+
+/// Reports missing and unexpected atoms of standard amino acids as well as chain breaks and
+/// missing residues. Missing hydrogens are only reported if the structure contains any.
+pub fn analyze_completeness(
+    pdb: &PDB,
+    topology: &Topology,
+) -> Result<(Table, Option<Table>, Option<Table>), anyhow::Error> {
+    let residues = check_completeness(pdb, topology);
+    ensure!(!residues.is_empty(), "No standard amino acids found!");
+    let hydrogens = pdb.atoms().any(|a| a.element().eq_ignore_ascii_case("H"));
+    let gaps = find_chain_gaps(pdb);
+
+    let new_table = || {
+        let mut table = Table::new();
+        table
+            .load_preset(UTF8_FULL)
+            .apply_modifier(UTF8_ROUND_CORNERS)
+            .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+        table
+    };
+    let count = |f: &dyn Fn(&ResidueCompleteness) -> bool| {
+        residues.iter().filter(|r| f(r)).count().to_string()
+    };
+
+    let mut summary_table = new_table();
+    summary_table.set_header(Row::from(vec!["", "Count"]));
+    summary_table.add_row(Row::from(vec![
+        "Amino acids checked".to_owned(),
+        residues.len().to_string(),
+    ]));
+    summary_table.add_row(Row::from(vec![
+        "Residues with missing atoms".to_owned(),
+        count(&|r| !r.missing.is_empty()),
+    ]));
+    summary_table.add_row(Row::from(vec![
+        "Residues with missing hydrogens".to_owned(),
+        if hydrogens {
+            count(&|r| !r.missing_hydrogens.is_empty())
+        } else {
+            "No hydrogens present".to_owned()
+        },
+    ]));
+    summary_table.add_row(Row::from(vec![
+        "Residues with unexpected atoms".to_owned(),
+        count(&|r| !r.unexpected.is_empty()),
+    ]));
+    summary_table.add_row(Row::from(vec![
+        "Chain breaks".to_owned(),
+        gaps.iter()
+            .filter(|g| g.kind != GapKind::NumberingGap)
+            .count()
+            .to_string(),
+    ]));
+    summary_table.add_row(Row::from(vec![
+        "Missing residues".to_owned(),
+        gaps.iter()
+            .map(|g| match g.kind {
+                GapKind::MissingResidues(n) => n,
+                _ => 0,
+            })
+            .sum::<usize>()
+            .to_string(),
+    ]));
+
+    let incomplete: Vec<&ResidueCompleteness> = residues
+        .iter()
+        .filter(|r| {
+            !r.missing.is_empty()
+                || !r.unexpected.is_empty()
+                || (hydrogens && !r.missing_hydrogens.is_empty())
+        })
+        .collect();
+    let residue_table = (!incomplete.is_empty()).then(|| {
+        let mut table = new_table();
+        table.set_header(Row::from(vec![
+            "Chain",
+            "Residue",
+            "Missing Atoms",
+            "Missing Hydrogens",
+            "Unexpected Atoms",
+        ]));
+        for residue in incomplete {
+            table.add_row(Row::from(vec![
+                residue.chain.clone(),
+                residue.residue.clone(),
+                residue.missing.join(", "),
+                if hydrogens {
+                    residue.missing_hydrogens.join(", ")
+                } else {
+                    "-".to_owned()
+                },
+                residue.unexpected.join(", "),
+            ]));
+        }
+        table
+    });
+
+    let gap_table = (!gaps.is_empty()).then(|| {
+        let mut table = new_table();
+        table.set_header(Row::from(vec![
+            "Chain",
+            "Residue",
+            "Next Residue",
+            "C-N Distance",
+            "Gap",
+        ]));
+        for gap in &gaps {
+            table.add_row(Row::from(vec![
+                gap.chain.clone(),
+                gap.before.clone(),
+                gap.after.clone(),
+                gap.distance.map_or("-".to_owned(), |d| format!("{:.2}", d)),
+                gap.kind.to_string(),
+            ]));
+        }
+        table
+    });
+
+    Ok((summary_table, residue_table, gap_table))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdbtbx::StrictnessLevel;
+
+    fn test_pdb(path: &str) -> PDB {
+        let (pdb, _) = pdbtbx::open_pdb(path, StrictnessLevel::Strict).unwrap();
+        pdb
+    }
+
+    #[test]
+    fn completeness_test() {
+        let pdb = test_pdb("tests/test_completeness.pdb");
+        let residues = check_completeness(&pdb, &Topology::charmm36_protein());
+        let atoms = |f: fn(&ResidueCompleteness) -> &Vec<String>| {
+            residues
+                .iter()
+                .map(|r| f(r).join(","))
+                .collect::<Vec<String>>()
+        };
+
+        // The N-terminal histidine lacks its third amine hydrogen, the tyrosine lacks its
+        // hydroxyl group, the glycine carries a CB and the glutamate at the end of the chain
+        // lacks the second carboxylate oxygen
+        let labels: Vec<&str> = residues.iter().map(|r| r.residue.as_str()).collect();
+        assert_eq!(labels, ["HIE 1", "GLY 2", "TYR 5", "VAL 6", "GLU 8"]);
+        assert_eq!(atoms(|r| &r.missing), ["", "", "OH", "", "OT2"]);
+        assert_eq!(atoms(|r| &r.missing_hydrogens), ["HT3", "", "HH", "", ""]);
+        assert_eq!(atoms(|r| &r.unexpected), ["", "CB", "", "", ""]);
+
+        let gaps = find_chain_gaps(&pdb);
+        assert_eq!(gaps.len(), 2);
+        assert_eq!(gaps[0].kind, GapKind::MissingResidues(2));
+        assert_eq!(
+            (gaps[0].before.as_str(), gaps[0].after.as_str()),
+            ("GLY 2", "TYR 5")
+        );
+        assert!(gaps[0].distance.unwrap() > PEPTIDE_BOND_DISTANCE);
+        assert_eq!(gaps[1].kind, GapKind::NumberingGap);
+        assert_eq!(gaps[1].after, "GLU 8");
+
+        // The unmodified peptide is only missing the third amine hydrogen and the C-terminal
+        // oxygen and has no gaps
+        let pdb = test_pdb("tests/test_blank.pdb");
+        assert!(find_chain_gaps(&pdb).is_empty());
+        let (_, residue_table, gap_table) =
+            analyze_completeness(&pdb, &Topology::charmm36_protein()).unwrap();
+        assert_eq!(residue_table.unwrap().row_iter().count(), 2);
+        assert!(gap_table.is_none());
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{formal_charges, in_region, protein_base_name, FormalCharge, Layer};
+use crate::options::Region;
+use crate::residue_names::is_protein;
+
+use comfy_table::modifiers::{UTF8_ROUND_CORNERS, UTF8_SOLID_INNER_BORDERS};
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{Row, Table};
+use pdbtbx::{
+    Atom, AtomConformerResidueChainModel, ContainsAtomConformer, ContainsAtomConformerResidue, PDB,
+};
+
+/// Maximum distance in A between the nitrogen and oxygen atoms of a salt bridge.
+const SALT_BRIDGE_DISTANCE: f64 = 4.0;
+
+/// Charged atoms of amino acid sidechains that can form salt bridges.
+fn salt_bridge_atoms(residue: &str) -> Option<(isize, &'static [&'static str])> {
+    match residue {
+        "ASP" => Some((-1, &["OD1", "OD2"])),
+        "GLU" => Some((-1, &["OE1", "OE2"])),
+        "LYS" => Some((1, &["NZ"])),
+        "ARG" => Some((1, &["NE", "NH1", "NH2"])),
+        "HIP" | "HSP" => Some((1, &["ND1", "NE2"])),
+        _ => None,
+    }
+}
+
+/// Region of an atom as shown in the environment tables. MM atoms are either active or frozen.
+fn region_label(atom: &Atom) -> &'static str {
+    match Layer::of(atom) {
+        Layer::QM1 => "QM1",
+        Layer::QM2 => "QM2",
+        Layer::MM if in_region(atom, Region::Active) => "Active",
+        Layer::MM => "Frozen",
+    }
+}
+
+/// Salt bridge between a carboxylate and a cationic sidechain given by the closest pair of
+/// oxygen and nitrogen atoms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SaltBridge {
+    pub anion: usize,
+    pub cation: usize,
+    pub distance: f64,
+}
+
+/// Charged group with its shortest distance to the selection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChargedGroup {
+    pub charge: FormalCharge,
+    pub distance: f64,
+}
+
+/// Returns the shortest distance to any of the given atoms for all atoms within the radius.
+fn distances_to(pdb: &PDB, atoms: &[usize], radius: f64) -> HashMap<usize, f64> {
+    let atom_set: HashSet<&usize> = atoms.iter().collect();
+    let tree = pdb.create_atom_rtree();
+    let mut distances: HashMap<usize, f64> = HashMap::new();
+
+    for origin in pdb
+        .atoms()
+        .filter(|a| atom_set.contains(&a.serial_number()))
+    {
+        for atom in tree.locate_within_distance(origin.pos(), radius.powi(2)) {
+            let d = origin.distance(atom);
+            distances
+                .entry(atom.serial_number())
+                .and_modify(|e| *e = e.min(d))
+                .or_insert(d);
+        }
+    }
+
+    distances
+}
+
+/// Finds all charged groups (see `formal_charges`) whose charged atom is within the radius of
+/// the given atoms, sorted by distance.
+pub fn find_charged_groups(pdb: &PDB, atoms: &[usize], radius: f64) -> Vec<ChargedGroup> {
+    let distances = distances_to(pdb, atoms, radius);
+    let mut groups: Vec<ChargedGroup> = formal_charges(pdb)
+        .into_iter()
+        .filter_map(|charge| {
+            distances
+                .get(&charge.atom)
+                .map(|&distance| ChargedGroup { charge, distance })
+        })
+        .collect();
+
+    groups.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+    groups
+}
+
+/// Finds salt bridges between ASP/GLU and LYS/ARG/HIP sidechains with an oxygen-nitrogen
+/// distance of at most 4 A. Only salt bridges with an atom within the radius of the given
+/// atoms are returned, sorted by distance.
+pub fn find_salt_bridges(pdb: &PDB, atoms: &[usize], radius: f64) -> Vec<SaltBridge> {
+    let environment = distances_to(pdb, atoms, radius);
+
+    // Atoms of anionic and cationic sidechains together with the index of their residue
+    let mut anions: Vec<(usize, &Atom)> = Vec::new();
+    let mut cations: HashMap<usize, usize> = HashMap::new();
+    let mut residue_atoms: Vec<Vec<usize>> = Vec::new();
+    for residue in pdb.residues() {
+        let name = residue.name().unwrap_or("").to_uppercase();
+        if !is_protein(&name) {
+            continue;
+        }
+        if let Some((charge, names)) = salt_bridge_atoms(protein_base_name(&name)) {
+            let index = residue_atoms.len();
+            residue_atoms.push(residue.atoms().map(|a| a.serial_number()).collect());
+            for atom in residue.atoms().filter(|a| names.contains(&a.name())) {
+                if charge < 0 {
+                    anions.push((index, atom))
+                } else {
+                    cations.insert(atom.serial_number(), index);
+                }
+            }
+        }
+    }
+
+    let tree = pdb.create_atom_rtree();
+    // Closest pair of atoms for every pair of residues
+    let mut bridges: HashMap<(usize, usize), SaltBridge> = HashMap::new();
+    for (anion_residue, anion) in anions {
+        for cation in tree.locate_within_distance(anion.pos(), SALT_BRIDGE_DISTANCE.powi(2)) {
+            if let Some(&cation_residue) = cations.get(&cation.serial_number()) {
+                let bridge = SaltBridge {
+                    anion: anion.serial_number(),
+                    cation: cation.serial_number(),
+                    distance: anion.distance(cation),
+                };
+                bridges
+                    .entry((anion_residue, cation_residue))
+                    .and_modify(|b| {
+                        if bridge.distance < b.distance {
+                            *b = bridge.clone()
+                        }
+                    })
+                    .or_insert(bridge);
+            }
+        }
+    }
+
+    let mut bridges: Vec<SaltBridge> = bridges
+        .into_iter()
+        .filter(|((a, c), _)| {
+            residue_atoms[*a]
+                .iter()
+                .chain(&residue_atoms[*c])
+                .any(|id| environment.contains_key(id))
+        })
+        .map(|(_, b)| b)
+        .collect();
+    bridges.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+    bridges
+}
+
+/// Lists the charged groups within the radius of the given atoms and the salt bridges in
+/// this environment, if any, together with the regions of the atoms involved.
+pub fn analyze_environment(
+    pdb: &PDB,
+    atoms: &[usize],
+    radius: f64,
+) -> Result<(Table, Option<Table>), anyhow::Error> {
+    let groups = find_charged_groups(pdb, atoms, radius);
+    ensure!(
+        !groups.is_empty(),
+        "No charged groups found within {} A of selection!",
+        radius
+    );
+
+    let atom_map: HashMap<usize, AtomConformerResidueChainModel> = pdb
+        .atoms_with_hierarchy()
+        .map(|a| (a.atom().serial_number(), a))
+        .collect();
+    let residue = |atom_hier: &AtomConformerResidueChainModel| {
+        format!(
+            "{} {}{}",
+            atom_hier.residue().name().unwrap_or(""),
+            atom_hier.residue().serial_number(),
+            atom_hier.residue().insertion_code().unwrap_or("")
+        )
+    };
+    let new_table = || {
+        let mut table = Table::new();
+        table
+            .load_preset(UTF8_FULL)
+            .apply_modifier(UTF8_ROUND_CORNERS)
+            .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+        table
+    };
+
+    let mut group_table = new_table();
+    group_table.set_header(Row::from(vec![
+        "Residue", "Group", "Atom ID", "Atom", "Charge", "Distance", "Region",
+    ]));
+    for group in groups {
+        let atom = &atom_map[&group.charge.atom];
+        group_table.add_row(Row::from(vec![
+            residue(atom),
+            group.charge.group.to_owned(),
+            group.charge.atom.to_string(),
+            atom.atom().name().to_owned(),
+            format!("{:+}", group.charge.charge),
+            format!("{:.2}", group.distance),
+            region_label(atom.atom()).to_owned(),
+        ]));
+    }
+
+    let bridges = find_salt_bridges(pdb, atoms, radius);
+    let bridge_table = (!bridges.is_empty()).then(|| {
+        let mut table = new_table();
+        table.set_header(Row::from(vec![
+            "Anion",
+            "Anion ID",
+            "Anion Region",
+            "Cation",
+            "Cation ID",
+            "Cation Region",
+            "Distance",
+        ]));
+        for bridge in bridges {
+            let (anion, cation) = (&atom_map[&bridge.anion], &atom_map[&bridge.cation]);
+            table.add_row(Row::from(vec![
+                format!("{} {}", residue(anion), anion.atom().name()),
+                bridge.anion.to_string(),
+                region_label(anion.atom()).to_owned(),
+                format!("{} {}", residue(cation), cation.atom().name()),
+                bridge.cation.to_string(),
+                region_label(cation.atom()).to_owned(),
+                format!("{:.2}", bridge.distance),
+            ]));
+        }
+        table
+    });
+
+    Ok((group_table, bridge_table))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdbtbx::StrictnessLevel;
+
+    fn test_pdb(path: &str) -> PDB {
+        let (pdb, _) = pdbtbx::open_pdb(path, StrictnessLevel::Strict).unwrap();
+        pdb
+    }
+
+    #[test]
+    fn environment_test() {
+        let mut pdb = test_pdb("tests/test_saltbridge.pdb");
+        for atom in pdb.atoms_mut() {
+            match atom.serial_number() {
+                6 => atom.set_occupancy(1.00).unwrap(),
+                14 => atom.set_b_factor(1.00).unwrap(),
+                _ => (),
+            }
+        }
+
+        // The aspartate itself, the lysine and the sodium ion are close, the arginine and
+        // glutamate are not
+        let asp = [1, 2, 3, 4];
+        let groups: Vec<(usize, isize)> = find_charged_groups(&pdb, &asp, 8.0)
+            .iter()
+            .map(|g| (g.charge.atom, g.charge.charge))
+            .collect();
+        assert_eq!(groups, vec![(2, -1), (6, 1), (14, 1)]);
+
+        let bridges = find_salt_bridges(&pdb, &asp, 8.0);
+        assert_eq!(bridges.len(), 1);
+        assert_eq!((bridges[0].anion, bridges[0].cation), (3, 6));
+        assert!((bridges[0].distance - 2.8).abs() < 1e-3);
+        assert_eq!(find_salt_bridges(&pdb, &asp, 20.0).len(), 2);
+
+        let atoms: HashMap<usize, &Atom> = pdb.atoms().map(|a| (a.serial_number(), a)).collect();
+        assert_eq!(region_label(atoms[&6]), "QM1");
+        assert_eq!(region_label(atoms[&14]), "Active");
+        assert_eq!(region_label(atoms[&2]), "Frozen");
+
+        assert!(analyze_environment(&pdb, &[7], 1.0).is_ok());
+        assert!(analyze_environment(&pdb, &[1], 1.0).is_err());
+    }
+}
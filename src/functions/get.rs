@@ -1,12 +1,19 @@
 use core::fmt;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
     options::{Partial, Region},
-    selection::{convert_result, parse_selection, Conjunction, Selection},
+    residue_names::{is_ion, is_ligand, is_nucleic, is_protein, is_water},
+    selection::{
+        convert_result, dependencies, parse_selection, Conjunction, Definitions, Expression, Macro,
+        Property, ResidueSpec, Selection,
+    },
 };
 
-use super::{parse_atomic_list, parse_residue_list};
+use super::{
+    find_metal_sites, get_sasa, get_secondary_structure, parse_atomic_list, parse_element_list,
+    parse_residue_list, BondGraph, ResidueList, SecondaryStructure, PROBE_RADIUS,
+};
 use anyhow::Result;
 use colored::Colorize;
 use comfy_table::{
@@ -17,7 +24,7 @@ use comfy_table::{
 use itertools::Itertools;
 use pdbtbx::{
     Atom, AtomConformerResidueChainModel, ContainsAtomConformer, ContainsAtomConformerResidue,
-    Residue, PDB,
+    ContainsAtomConformerResidueChain, PDB,
 };
 use rayon::{iter::FromParallelIterator, prelude::ParallelIterator};
 
@@ -139,24 +146,254 @@ fn get_residue_sphere(
     Ok(sphere_atoms)
 }
 
-// Get list of atom IDs for printing to stdout or file
-pub fn get_atomlist(pdb: &PDB, region: Region) -> Result<AtomList, anyhow::Error> {
-    let filt_closure = match region {
-        Region::QM1 => |a: &Atom| a.occupancy() == 1.00,
-        Region::QM2 => |a: &Atom| a.occupancy() == 2.00,
-        Region::Active => |a: &Atom| a.b_factor() == 1.00,
+/// Takes a list of Atom IDs and a radius in A. Returns a Vector of Atom IDs of Atoms within the given
+/// radius of any of the given Atoms, including these. If 'byres' is set, all Atoms of Residues with at
+/// least one Atom within the radius are returned, respecting the requested partial mode.
+pub fn get_atoms_within(
+    pdb: &PDB,
+    atomlist: &[usize],
+    radius: f64,
+    byres: bool,
+    partial: Option<Partial>,
+) -> AtomList {
+    let atom_set: HashSet<&usize> = atomlist.iter().collect();
+    let origins: Vec<(f64, f64, f64)> = pdb
+        .atoms()
+        .filter(|a| atom_set.contains(&a.serial_number()))
+        .map(Atom::pos)
+        .collect();
+
+    if byres {
+        let tree = pdb.create_hierarchy_rtree();
+        let residues: ResidueList = origins
+            .iter()
+            .flat_map(|origin| tree.locate_within_distance(*origin, radius.powi(2)))
+            .map(|atom_hier| {
+                (
+                    atom_hier.chain().id(),
+                    atom_hier.residue().serial_number(),
+                    atom_hier.residue().insertion_code(),
+                )
+            })
+            .unique()
+            .collect();
+
+        get_atomlist_from_residuelist(&residues, pdb, partial)
+    } else {
+        let tree = pdb.create_atom_rtree();
+        origins
+            .iter()
+            .flat_map(|origin| tree.locate_within_distance(*origin, radius.powi(2)))
+            .map(|atom| atom.serial_number())
+            .unique()
+            .sorted()
+            .collect()
+    }
+}
+
+/// Chain ID, Residue ID and insertion code of the Residue of an Atom.
+fn residue_key<'a>(
+    atom_hier: &'a AtomConformerResidueChainModel,
+) -> (&'a str, isize, Option<&'a str>) {
+    (
+        atom_hier.chain().id(),
+        atom_hier.residue().serial_number(),
+        atom_hier.residue().insertion_code(),
+    )
+}
+
+/// Takes a list of Atom IDs and returns the 'count' Atoms closest to any of them, excluding these. If
+/// 'byres' is set, all Atoms of the 'count' closest Residues are returned, excluding the Residues of
+/// the given Atoms and respecting the requested partial mode. The distance of a Residue is given by its closest Atom. Ties are broken by Atom ID
+/// or by chain ID, Residue ID and insertion code.
+fn get_nearest(
+    pdb: &PDB,
+    atomlist: &[usize],
+    count: usize,
+    byres: bool,
+    partial: Option<Partial>,
+) -> AtomList {
+    let atom_set: HashSet<&usize> = atomlist.iter().collect();
+    let tree = pdb.create_hierarchy_rtree();
+    let origins: Vec<&AtomConformerResidueChainModel> = tree
+        .iter()
+        .filter(|a| atom_set.contains(&a.atom().serial_number()))
+        .collect();
+    let residue_set: HashSet<(&str, isize, Option<&str>)> = if byres {
+        origins.iter().map(|a| residue_key(a)).collect()
+    } else {
+        HashSet::new()
     };
+    // Candidates are keyed by chain ID, Residue ID and insertion code in residue mode and by
+    // Atom ID otherwise so the keys can be ordered for breaking ties.
+    let mut distances: HashMap<(&str, isize, Option<&str>, usize), f64> = HashMap::new();
 
-    let num_vec = pdb
-        .par_atoms()
-        .filter(|&a| filt_closure(a))
+    // The closest candidates to the whole list must be among the closest ones of each single Atom
+    for origin in origins {
+        let mut found = HashSet::new();
+        let mut last_distance = 0.0;
+
+        for (atom_hier, distance) in
+            tree.nearest_neighbor_iter_with_distance_2(&origin.atom().pos())
+        {
+            if atom_set.contains(&atom_hier.atom().serial_number())
+                || residue_set.contains(&residue_key(atom_hier))
+            {
+                continue;
+            }
+            if found.len() >= count && distance > last_distance {
+                break;
+            }
+
+            let key = if byres {
+                let (chain, serial, insertion_code) = residue_key(atom_hier);
+                (chain, serial, insertion_code, 0)
+            } else {
+                ("", 0, None, atom_hier.atom().serial_number())
+            };
+            found.insert(key);
+            last_distance = distance;
+
+            let entry = distances.entry(key).or_insert(distance);
+            *entry = entry.min(distance);
+        }
+    }
+
+    let nearest = distances
+        .into_iter()
+        .sorted_by(|(k1, d1), (k2, d2)| d1.partial_cmp(d2).unwrap().then(k1.cmp(k2)))
+        .take(count)
+        .map(|(k, _)| k);
+
+    if byres {
+        let residues: ResidueList = nearest.map(|(c, s, i, _)| (c, s, i)).collect();
+        get_atomlist_from_residuelist(&residues, pdb, partial)
+    } else {
+        filter_partial(nearest.map(|(_, _, _, a)| a).collect(), pdb, partial)
+    }
+}
+
+/// Takes a list of Atom IDs and returns the metal Atoms among them together with all Residues
+/// coordinating them. The requested partial mode is only applied to protein Residues so that
+/// coordinating waters and ligands are always included as a whole.
+fn get_metalsite_atoms(pdb: &PDB, atomlist: &[usize], partial: Option<Partial>) -> AtomList {
+    let sites = find_metal_sites(pdb, atomlist);
+    let metals: HashSet<usize> = sites.iter().map(|s| s.metal).collect();
+    let ligands: HashSet<usize> = sites
+        .iter()
+        .flat_map(|s| s.ligands.iter().map(|(id, _)| *id))
+        .collect();
+
+    let atoms: Vec<AtomConformerResidueChainModel> = pdb.atoms_with_hierarchy().collect();
+    let residues: HashSet<(&str, isize, Option<&str>)> = atoms
+        .iter()
+        .filter(|a| ligands.contains(&a.atom().serial_number()))
+        .map(|a| {
+            (
+                a.chain().id(),
+                a.residue().serial_number(),
+                a.residue().insertion_code(),
+            )
+        })
+        .collect();
+
+    atoms
+        .iter()
+        .filter(|a| {
+            let in_residue = residues.contains(&(
+                a.chain().id(),
+                a.residue().serial_number(),
+                a.residue().insertion_code(),
+            ));
+            let is_protein_residue = is_protein(a.residue().name().unwrap_or(""));
+            metals.contains(&a.atom().serial_number())
+                || (in_residue && (!is_protein_residue || matches_partial(a, partial)))
+        })
+        .map(|a| a.atom().serial_number())
+        .collect()
+}
+
+/// Checks whether an atom belongs to the given region. QM1 and QM2 atoms are marked by an
+/// occupancy of 1 and 2, respectively, active atoms by a B-factor of 1.
+pub fn in_region(atom: &Atom, region: Region) -> bool {
+    match region {
+        Region::QM1 => atom.occupancy() == 1.00,
+        Region::QM2 => atom.occupancy() == 2.00,
+        Region::Active => atom.b_factor() == 1.00,
+    }
+}
+
+// Get list of atom IDs in the given region which may be empty
+fn get_region_atoms(pdb: &PDB, region: Region) -> AtomList {
+    pdb.par_atoms()
+        .filter(|a| in_region(a, region))
         .map(Atom::serial_number)
-        .collect::<Vec<usize>>();
+        .collect::<Vec<usize>>()
+}
+
+// Get list of atom IDs for printing to stdout or file
+pub fn get_atomlist(pdb: &PDB, region: Region) -> Result<AtomList, anyhow::Error> {
+    let num_vec = get_region_atoms(pdb, region);
 
     ensure!(!num_vec.is_empty(), "No atoms in the requested region!");
     Ok(num_vec)
 }
 
+/// Returns the QM1 and QM2 atoms together with all whole residues that have an atom within the
+/// given radius of any QM atom. If 'max_atoms' is given, residues are added by increasing distance
+/// to the QM atoms until the next one would exceed the limit. QM atoms are always included.
+pub fn get_active_around_qm(
+    pdb: &PDB,
+    radius: f64,
+    max_atoms: Option<usize>,
+) -> Result<AtomList, anyhow::Error> {
+    let qm_atoms: Vec<&Atom> = pdb
+        .atoms()
+        .filter(|a| in_region(a, Region::QM1) || in_region(a, Region::QM2))
+        .collect();
+    ensure!(!qm_atoms.is_empty(), "No atoms in the QM regions!");
+
+    // Residues within the radius with their shortest distance to any QM atom
+    let tree = pdb.create_hierarchy_rtree();
+    let mut residues: HashMap<(&str, isize, Option<&str>), (f64, AtomList)> = HashMap::new();
+    for qm_atom in &qm_atoms {
+        for atom_hier in tree.locate_within_distance(qm_atom.pos(), radius.powi(2)) {
+            let distance = qm_atom.distance(atom_hier.atom());
+            residues
+                .entry((
+                    atom_hier.chain().id(),
+                    atom_hier.residue().serial_number(),
+                    atom_hier.residue().insertion_code(),
+                ))
+                .and_modify(|(d, _)| *d = d.min(distance))
+                .or_insert_with(|| {
+                    (
+                        distance,
+                        atom_hier
+                            .residue()
+                            .atoms()
+                            .map(Atom::serial_number)
+                            .collect(),
+                    )
+                });
+        }
+    }
+    let residues = residues
+        .into_values()
+        .sorted_by(|(d1, a1), (d2, a2)| d1.total_cmp(d2).then_with(|| a1.cmp(a2)));
+
+    let mut active: HashSet<usize> = qm_atoms.iter().map(|a| a.serial_number()).collect();
+    for (_, atoms) in residues {
+        let new_atoms = atoms.iter().filter(|a| !active.contains(a)).count();
+        if matches!(max_atoms, Some(m) if active.len() + new_atoms > m) {
+            break;
+        }
+        active.extend(atoms)
+    }
+
+    Ok(active.into_iter().sorted().collect())
+}
+
 // Get list of residue IDs for printing to stdout or file
 // pub fn get_residuelist(pdb: &PDB, region: Region) -> Result<ResidueList, anyhow::Error> {
 //     let filt_closure = match region {
@@ -176,27 +413,89 @@ pub fn get_atomlist(pdb: &PDB, region: Region) -> Result<AtomList, anyhow::Error
 //     Ok(num_vec)
 // }
 
+/// Checks whether an atom belongs to the requested part of its residue.
+fn matches_partial(atom_hier: &AtomConformerResidueChainModel, partial: Option<Partial>) -> bool {
+    match partial {
+        None => true,
+        Some(Partial::Backbone) => atom_hier.is_backbone(),
+        Some(Partial::Sidechain) => atom_hier.is_sidechain(),
+    }
+}
+
 fn get_atomlist_from_residuelist(
-    list: &[isize],
+    list: &ResidueList,
     pdb: &PDB,
     partial: Option<Partial>,
 ) -> Vec<usize> {
-    let residue_set: HashSet<&isize> = list.iter().collect();
-    match partial {
-        None => pdb
-            .atoms_with_hierarchy()
-            .filter(|a| residue_set.contains(&a.residue().serial_number()))
-            .map(|a| a.atom().serial_number())
-            .collect(),
-        Some(p) => pdb
-            .atoms_with_hierarchy()
-            .filter(|a| match p {
-                Partial::Backbone => a.is_backbone(),
-                Partial::Sidechain => a.is_sidechain(),
-            } && residue_set.contains(&a.residue().serial_number()))
-            .map(|a| a.atom().serial_number())
-            .collect(),
+    let residue_set: HashSet<&(&str, isize, Option<&str>)> = list.iter().collect();
+    let in_set = |a: &AtomConformerResidueChainModel| {
+        residue_set.contains(&(
+            a.chain().id(),
+            a.residue().serial_number(),
+            a.residue().insertion_code(),
+        ))
+    };
+
+    pdb.atoms_with_hierarchy()
+        .filter(|a| matches_partial(a, partial) && in_set(a))
+        .map(|a| a.atom().serial_number())
+        .collect()
+}
+
+/// Returns all atoms of the given chains, respecting the requested partial mode.
+fn get_atomlist_from_chainlist(
+    list: &[&str],
+    pdb: &PDB,
+    partial: Option<Partial>,
+) -> Result<Vec<usize>, anyhow::Error> {
+    let chain_set: HashSet<&str> = list.iter().copied().collect();
+    let pdb_set: HashSet<&str> = pdb.chains().map(|c| c.id()).collect();
+    let mut missing_chains = chain_set.difference(&pdb_set).peekable();
+
+    ensure!(
+        missing_chains.peek().is_none(),
+        "No chain(s) found with identifier(s): {}",
+        missing_chains.format(",")
+    );
+
+    Ok(pdb
+        .atoms_with_hierarchy()
+        .filter(|a| chain_set.contains(a.chain().id()) && matches_partial(a, partial))
+        .map(|a| a.atom().serial_number())
+        .collect())
+}
+
+/// Removes all atoms from the given list that do not belong to the requested part of their residue.
+fn filter_partial(atomlist: Vec<usize>, pdb: &PDB, partial: Option<Partial>) -> Vec<usize> {
+    if partial.is_none() {
+        return atomlist;
     }
+    let atom_set: HashSet<usize> = atomlist.into_iter().collect();
+    pdb.atoms_with_hierarchy()
+        .filter(|a| atom_set.contains(&a.atom().serial_number()) && matches_partial(a, partial))
+        .map(|a| a.atom().serial_number())
+        .collect()
+}
+
+/// Returns all atoms belonging to the given predefined selection, respecting the requested
+/// partial mode.
+fn get_macro_atoms(pdb: &PDB, selection: Macro, partial: Option<Partial>) -> Vec<usize> {
+    pdb.atoms_with_hierarchy()
+        .filter(|a| {
+            let name = a.residue().name().unwrap_or("");
+            matches_partial(a, partial)
+                && match selection {
+                    Macro::Protein => is_protein(name),
+                    Macro::Nucleic => is_nucleic(name),
+                    Macro::Water => is_water(name),
+                    Macro::Ion => is_ion(name),
+                    Macro::Ligand => is_ligand(name),
+                    Macro::Backbone => is_protein(name) && a.is_backbone(),
+                    Macro::Sidechain => is_protein(name) && a.is_sidechain(),
+                }
+        })
+        .map(|a| a.atom().serial_number())
+        .collect()
 }
 
 fn get_inverted(atomlist: &[usize], pdb: &PDB) -> Vec<usize> {
@@ -221,111 +520,282 @@ fn verify_atomlist(list: &[usize], pdb: &PDB) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-fn verify_residuelist(list: &[isize], pdb: &PDB) -> Result<(), anyhow::Error> {
-    let output_set: HashSet<isize> = list.iter().copied().collect();
-    let pdb_set: HashSet<isize> =
-        HashSet::from_par_iter(pdb.par_residues().map(Residue::serial_number));
-    let mut missing_atoms = output_set.difference(&pdb_set).peekable();
+/// Resolves residue specifications from user input to the residues present in the PDB. Fails if
+/// any specification does not match at least one residue.
+fn verify_residuelist<'a>(
+    list: &[ResidueSpec],
+    pdb: &'a PDB,
+) -> Result<ResidueList<'a>, anyhow::Error> {
+    let mut found = vec![false; list.len()];
+    let mut residues = Vec::new();
+
+    for chain in pdb.chains() {
+        for residue in chain.residues() {
+            let mut matched = false;
+            for (i, spec) in list.iter().enumerate() {
+                if spec.matches(
+                    chain.id(),
+                    residue.serial_number(),
+                    residue.insertion_code(),
+                ) {
+                    found[i] = true;
+                    matched = true;
+                }
+            }
+            if matched {
+                residues.push((
+                    chain.id(),
+                    residue.serial_number(),
+                    residue.insertion_code(),
+                ));
+            }
+        }
+    }
+
+    let mut missing_residues = list
+        .iter()
+        .zip(found)
+        .filter(|(_, f)| !f)
+        .map(|(r, _)| r)
+        .peekable();
 
     ensure!(
-        missing_atoms.peek().is_none(),
+        missing_residues.peek().is_none(),
         "No residue(s) found with serial number(s): {}",
-        missing_atoms.format(",")
+        missing_residues.format(",")
     );
 
-    Ok(())
+    Ok(residues)
+}
+
+/// Per-atom properties that are expensive to compute. They are calculated at most once while
+/// evaluating a selection, no matter how often the selection refers to them.
+#[derive(Default)]
+struct PropertyCache {
+    sasa: Option<HashMap<usize, f64>>,
+    secondary_structure: Option<HashMap<usize, SecondaryStructure>>,
+}
+
+impl PropertyCache {
+    fn sasa(&mut self, pdb: &PDB) -> &HashMap<usize, f64> {
+        self.sasa.get_or_insert_with(|| get_sasa(pdb, PROBE_RADIUS))
+    }
+
+    fn secondary_structure(&mut self, pdb: &PDB) -> &HashMap<usize, SecondaryStructure> {
+        self.secondary_structure
+            .get_or_insert_with(|| get_secondary_structure(pdb))
+    }
 }
 
 fn get_atoms_from_selection(
     s: Selection,
     pdb: &PDB,
     partial: Option<Partial>,
+    definitions: &Definitions,
+    cache: &mut PropertyCache,
 ) -> Result<Vec<usize>, anyhow::Error> {
-    let to_invert: bool;
-    let mut atomvec = match s {
-        Selection::ID { atomlist, invert } => {
-            to_invert = invert;
+    let atomvec = match s {
+        Selection::ID { atomlist } => {
             verify_atomlist(&atomlist, pdb)?;
             atomlist
         }
-        Selection::Name { atomlist, invert } => {
-            to_invert = invert;
-            parse_atomic_list(&atomlist.join(","), pdb)?
-        }
-        Selection::Resid { reslist, invert } => {
-            to_invert = invert;
-            verify_residuelist(&reslist, pdb)?;
-            get_atomlist_from_residuelist(&reslist, pdb, partial)
+        Selection::Name { atomlist } => parse_atomic_list(&atomlist, pdb)?,
+        Selection::Resid { reslist } => {
+            let res_list = verify_residuelist(&reslist, pdb)?;
+            get_atomlist_from_residuelist(&res_list, pdb, partial)
         }
-        Selection::Resname { reslist, invert } => {
-            to_invert = invert;
-            let res_list = parse_residue_list(&reslist.join(","), pdb)?;
+        Selection::Resname { reslist } => {
+            let res_list = parse_residue_list(&reslist, pdb)?;
             get_atomlist_from_residuelist(&res_list, pdb, partial)
         }
-        Selection::Sphere {
-            origin,
-            radius,
-            invert,
+        Selection::Chain { chainlist } => get_atomlist_from_chainlist(&chainlist, pdb, partial)?,
+        Selection::Sphere { origin, radius } => get_atom_sphere(pdb, origin, radius, false)?,
+        Selection::ResSphere { origin, radius } => get_residue_sphere(pdb, origin, radius, false)?,
+        Selection::Element { elementlist } => parse_element_list(&elementlist, pdb)?,
+        Selection::Hetero => pdb
+            .par_atoms()
+            .filter(|a| a.hetero())
+            .map(Atom::serial_number)
+            .collect(),
+        Selection::Comparison {
+            property,
+            comparator,
+            value,
         } => {
-            to_invert = invert;
-            get_atom_sphere(pdb, origin, radius, false)?
+            let sasa = matches!(property, Property::Sasa).then(|| cache.sasa(pdb));
+            pdb.par_atoms()
+                .filter(|a| {
+                    let atom_value = match property {
+                        Property::X => Some(a.x()),
+                        Property::Y => Some(a.y()),
+                        Property::Z => Some(a.z()),
+                        Property::BFactor => Some(a.b_factor()),
+                        Property::Occupancy => Some(a.occupancy()),
+                        // Water molecules are considered solvent and have no surface area
+                        Property::Sasa => sasa.and_then(|s| s.get(&a.serial_number()).copied()),
+                    };
+                    matches!(atom_value, Some(v) if comparator.compare(v, value))
+                })
+                .map(Atom::serial_number)
+                .collect()
         }
-        Selection::ResSphere {
-            origin,
-            radius,
-            invert,
-        } => {
-            to_invert = invert;
-            get_residue_sphere(pdb, origin, radius, false)?
+        Selection::Region(region) => get_region_atoms(pdb, region),
+        // All atoms that are neither part of QM1 nor QM2
+        Selection::MM => pdb
+            .par_atoms()
+            .filter(|a| a.occupancy() != 1.00 && a.occupancy() != 2.00)
+            .map(Atom::serial_number)
+            .collect(),
+        Selection::Macro(m) => get_macro_atoms(pdb, m, partial),
+        Selection::SecondaryStructure(ss) => {
+            let structure = cache.secondary_structure(pdb);
+            pdb.atoms_with_hierarchy()
+                .filter(|a| {
+                    structure.get(&a.atom().serial_number()) == Some(&ss)
+                        && matches_partial(a, partial)
+                })
+                .map(|a| a.atom().serial_number())
+                .collect()
+        }
+        Selection::Named(name) => {
+            let input = definitions
+                .get(name)
+                .ok_or_else(|| anyhow!("No selection defined with name: {}", name))?;
+            get_atoms_from_input(input, pdb, partial, definitions, cache)?
         }
     };
 
-    if to_invert {
-        atomvec = get_inverted(&atomvec, pdb);
-    }
     Ok(atomvec)
 }
 
+/// Recursively evaluates a selection expression tree to a list of atom IDs.
+fn get_atoms_from_expression(
+    expression: Expression,
+    pdb: &PDB,
+    partial: Option<Partial>,
+    definitions: &Definitions,
+    cache: &mut PropertyCache,
+) -> Result<Vec<usize>, anyhow::Error> {
+    match expression {
+        Expression::Selection(s) => get_atoms_from_selection(s, pdb, partial, definitions, cache),
+        Expression::Not(e) => Ok(get_inverted(
+            &get_atoms_from_expression(*e, pdb, partial, definitions, cache)?,
+            pdb,
+        )),
+        Expression::Conjunction(left, conj, right) => {
+            let left_set: HashSet<usize> = HashSet::from_iter(get_atoms_from_expression(
+                *left,
+                pdb,
+                partial,
+                definitions,
+                cache,
+            )?);
+            let right_set: HashSet<usize> = HashSet::from_iter(get_atoms_from_expression(
+                *right,
+                pdb,
+                partial,
+                definitions,
+                cache,
+            )?);
+
+            Ok(match conj {
+                Conjunction::Or => left_set.union(&right_set).copied().collect(),
+                Conjunction::And => left_set.intersection(&right_set).copied().collect(),
+            })
+        }
+        // The inner selection is evaluated without partial mode so the spatial reference is complete
+        Expression::Within {
+            radius,
+            byres,
+            inner,
+        } => Ok(get_atoms_within(
+            pdb,
+            &get_atoms_from_expression(*inner, pdb, None, definitions, cache)?,
+            radius,
+            byres,
+            partial,
+        )),
+        Expression::Nearest {
+            count,
+            byres,
+            inner,
+        } => Ok(get_nearest(
+            pdb,
+            &get_atoms_from_expression(*inner, pdb, None, definitions, cache)?,
+            count,
+            byres,
+            partial,
+        )),
+        // Bond-based selections evaluate their inner selection without partial mode as well
+        Expression::BondedTo(inner) => {
+            let atoms = get_atoms_from_expression(*inner, pdb, None, definitions, cache)?;
+            let bonded = BondGraph::new(pdb).bonded_to(&atoms);
+            Ok(filter_partial(bonded, pdb, partial))
+        }
+        Expression::NBonds { bonds, inner } => {
+            let atoms = get_atoms_from_expression(*inner, pdb, None, definitions, cache)?;
+            let bonded = BondGraph::new(pdb).within_bonds(&atoms, bonds);
+            Ok(filter_partial(bonded, pdb, partial))
+        }
+        Expression::Fragment(inner) => {
+            let atoms = get_atoms_from_expression(*inner, pdb, None, definitions, cache)?;
+            let fragments = BondGraph::new(pdb).fragments(&atoms);
+            Ok(filter_partial(fragments, pdb, partial))
+        }
+        Expression::MetalSite(inner) => {
+            let atoms = get_atoms_from_expression(*inner, pdb, None, definitions, cache)?;
+            Ok(get_metalsite_atoms(pdb, &atoms, partial))
+        }
+    }
+}
+
 pub fn get_atomlist_from_input(
     input: &str,
     pdb: &PDB,
     partial: Option<Partial>,
+    definitions: &Definitions,
+) -> Result<Vec<usize>, anyhow::Error> {
+    get_atoms_from_input(
+        input,
+        pdb,
+        partial,
+        definitions,
+        &mut PropertyCache::default(),
+    )
+}
+
+fn get_atoms_from_input(
+    input: &str,
+    pdb: &PDB,
+    partial: Option<Partial>,
+    definitions: &Definitions,
+    cache: &mut PropertyCache,
 ) -> Result<Vec<usize>, anyhow::Error> {
     // Add a space to the given user input. This will make pest parse the
     // last character as a finished word resulting in more meaningful error messages.
     let input = input.to_owned() + " ";
-    let (sele_vec, conj_vec) = convert_result(parse_selection(&input), &input)?;
+    let expression = convert_result(parse_selection(&input), &input)?;
 
-    let mut sele_iter = sele_vec.into_iter();
-    let initial_sel = sele_iter.next().unwrap();
-    let initial_atomvec = get_atoms_from_selection(initial_sel, pdb, partial)?;
+    get_atoms_from_expression(expression, pdb, partial, definitions, cache)
+}
 
-    if conj_vec.is_empty()
-    // if vec of conjunctions is not present, the vec of selections can only have one element
-    {
-        Ok(initial_atomvec)
-    } else {
-        let atom_set = sele_iter.zip(conj_vec.iter()).try_fold(
-            HashSet::from_iter(initial_atomvec),
-            |acc, (sele, conj)| -> Result<HashSet<usize>, anyhow::Error> {
-                match conj {
-                    Conjunction::Or => Ok(acc
-                        .union(&HashSet::from_iter(get_atoms_from_selection(
-                            sele, pdb, partial,
-                        )?))
-                        .copied()
-                        .collect()),
-                    Conjunction::And => Ok(acc
-                        .intersection(&HashSet::from_iter(get_atoms_from_selection(
-                            sele, pdb, partial,
-                        )?))
-                        .copied()
-                        .collect()),
-                }
-            },
-        )?;
-        Ok(atom_set.into_iter().collect())
-    }
+/// Stores the given selection under the given name after making sure that it can be evaluated
+/// and does not refer to itself.
+pub fn define_selection(
+    name: &str,
+    input: &str,
+    pdb: &PDB,
+    definitions: &mut Definitions,
+) -> Result<(), anyhow::Error> {
+    ensure!(
+        !dependencies(input, definitions).contains(&name),
+        "Selection '{}' must not refer to itself",
+        name
+    );
+    get_atomlist_from_input(input, pdb, None, definitions)?;
+    definitions.insert(name.to_owned(), input.to_owned());
+
+    Ok(())
 }
 
 pub fn get_measurements(
@@ -399,6 +869,18 @@ mod tests {
         pdb
     }
 
+    fn select_partial(pdb: &PDB, input: &str, partial: Option<Partial>) -> Vec<usize> {
+        get_atomlist_from_input(input, pdb, partial, &Definitions::new())
+            .unwrap()
+            .into_iter()
+            .sorted()
+            .collect()
+    }
+
+    fn select(pdb: &PDB, input: &str) -> Vec<usize> {
+        select_partial(pdb, input, None)
+    }
+
     #[test]
     fn atom_sphere_test() {
         let pdb = test_pdb("tests/test_blank.pdb");
@@ -454,6 +936,263 @@ mod tests {
         assert_eq!(active_atoms, vec![1, 2, 3, 5, 6, 8, 9]);
     }
 
+    #[test]
+    fn active_around_qm_test() {
+        let pdb = test_pdb("tests/test_get_atomlist.pdb");
+        let residue_atoms = |residues: &[isize]| -> Vec<usize> {
+            pdb.residues()
+                .filter(|r| residues.contains(&r.serial_number()))
+                .flat_map(|r| r.atoms().map(Atom::serial_number))
+                .collect()
+        };
+
+        // All QM atoms are part of residue 1
+        assert_eq!(
+            get_active_around_qm(&pdb, 0.0, None).unwrap(),
+            residue_atoms(&[1])
+        );
+        assert_eq!(
+            get_active_around_qm(&pdb, 3.0, None).unwrap(),
+            residue_atoms(&[1, 2])
+        );
+        // Residues exceeding the limit are dropped but QM atoms are kept
+        assert_eq!(
+            get_active_around_qm(&pdb, 3.0, Some(10)).unwrap(),
+            vec![1, 2, 4, 5, 6, 8, 9, 11, 12]
+        );
+        assert_eq!(
+            get_active_around_qm(&pdb, 3.0, Some(18)).unwrap(),
+            residue_atoms(&[1])
+        );
+
+        let pdb = test_pdb("tests/test_blank.pdb");
+        assert!(get_active_around_qm(&pdb, 3.0, None).is_err());
+    }
+
+    #[test]
+    fn chain_selection_test() {
+        let pdb = test_pdb("tests/test_chains.pdb");
+
+        assert_eq!(select(&pdb, "chain B"), (26..=57).collect::<Vec<usize>>());
+        assert_eq!(
+            select(&pdb, "resid A:1-2"),
+            (1..=25).collect::<Vec<usize>>()
+        );
+        assert_eq!(select(&pdb, "resid B:2"), (44..=57).collect::<Vec<usize>>());
+        assert_eq!(
+            select(&pdb, "resid B:2A"),
+            (51..=57).collect::<Vec<usize>>()
+        );
+        assert_eq!(
+            select(&pdb, "resid 2"),
+            (19..=25).chain(44..=57).collect::<Vec<usize>>()
+        );
+        assert_eq!(select(&pdb, "resid A:1,B:2A and name N"), vec![1, 51]);
+        assert!(get_atomlist_from_input("chain C", &pdb, None, &Definitions::new()).is_err());
+        assert!(get_atomlist_from_input("resid A:2A", &pdb, None, &Definitions::new()).is_err());
+    }
+
+    #[test]
+    fn selection_precedence_test() {
+        let pdb = test_pdb("tests/test_blank.pdb");
+
+        // 'and' binds stronger than 'or'
+        assert_eq!(
+            select(&pdb, "resid 7 or resid 6 and name O"),
+            vec![78, 81, 82, 83]
+        );
+        assert_eq!(
+            select(&pdb, "(resid 7 or resid 6) and name O"),
+            vec![78, 81]
+        );
+        assert_eq!(
+            select(&pdb, "resid 6,7 and not (name O or id 83)"),
+            vec![79, 80, 82]
+        );
+        assert_eq!(select(&pdb, "!(resid 1-6) and name h1"), vec![82]);
+        assert!(
+            get_atomlist_from_input("(resid 7 or resid 6", &pdb, None, &Definitions::new())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn region_selection_test() {
+        let pdb = test_pdb("tests/test_get_atomlist.pdb");
+
+        assert_eq!(select(&pdb, "qm1"), vec![1, 2, 4, 5, 6]);
+        assert_eq!(select(&pdb, "QM2 or id 1"), vec![1, 8, 9, 11, 12]);
+        assert_eq!(select(&pdb, "active and not qm1"), vec![3, 8, 9]);
+        assert_eq!(select(&pdb, "mm and active"), vec![3]);
+        assert_eq!(select(&pdb, "mm").len(), 74);
+    }
+
+    #[test]
+    fn within_selection_test() {
+        let pdb = test_pdb("tests/test_blank.pdb");
+
+        assert_eq!(
+            select(&pdb, "within 3 of id 26"),
+            get_atom_sphere(&pdb, 26, 3.0, true).unwrap()
+        );
+        assert_eq!(
+            select(&pdb, "byres within 4 of (id 26)"),
+            get_residue_sphere(&pdb, 26, 4.0, true)
+                .unwrap()
+                .into_iter()
+                .sorted()
+                .collect::<Vec<usize>>()
+        );
+        assert_eq!(
+            select(&pdb, "within 3 of (id 26 or id 81) and not resid 3"),
+            vec![21, 22, 23, 24, 25, 81, 82, 83]
+        );
+    }
+
+    #[test]
+    fn property_selection_test() {
+        let pdb = test_pdb("tests/test_get_atomlist.pdb");
+
+        assert_eq!(select(&pdb, "occupancy == 2"), vec![8, 9, 11, 12]);
+        assert_eq!(
+            select(&pdb, "bfactor>0.5 and occupancy != 1"),
+            vec![3, 8, 9]
+        );
+        assert_eq!(select(&pdb, "element n and x < 67"), vec![1, 10, 13]);
+        assert_eq!(select(&pdb, "element O and resid 6,7"), vec![78, 81]);
+        assert_eq!(select(&pdb, "hetero or id 1"), vec![1]);
+
+        // The isolated carbon is fully exposed, the water has no surface area at all
+        let pdb = test_pdb("tests/test_sasa.pdb");
+        assert_eq!(select(&pdb, "sasa > 100"), vec![1]);
+        assert_eq!(select(&pdb, "SASA<=100"), vec![2, 3]);
+        assert!(select(&pdb, "sasa < 0").is_empty());
+        assert_eq!(select(&pdb, "sasa > 100 or not sasa >= 0"), vec![1, 4]);
+    }
+
+    #[test]
+    fn macro_selection_test() {
+        let pdb = test_pdb("tests/test_blank.pdb");
+
+        assert_eq!(select(&pdb, "water"), (78..=83).collect::<Vec<usize>>());
+        assert_eq!(select(&pdb, "protein"), (1..=77).collect::<Vec<usize>>());
+        assert_eq!(select(&pdb, "not (protein or water)"), Vec::<usize>::new());
+        assert!(select(&pdb, "ligand or ion or nucleic").is_empty());
+        assert_eq!(
+            select(&pdb, "backbone and resid 1"),
+            vec![1, 2, 3, 4, 5, 17, 18]
+        );
+        assert_eq!(
+            select(&pdb, "sidechain or backbone"),
+            (1..=77).collect::<Vec<usize>>()
+        );
+    }
+
+    #[test]
+    fn name_pattern_selection_test() {
+        let pdb = test_pdb("tests/test_blank.pdb");
+
+        assert_eq!(select(&pdb, "name re:\"^C[AB]$\" and resid 1"), vec![4, 6]);
+        assert_eq!(select(&pdb, "resn HI? and name HB*,HD?"), vec![7, 8, 16]);
+        assert_eq!(select(&pdb, "(name H*) and water"), vec![79, 80, 82, 83]);
+        assert!(get_atomlist_from_input("name H1'", &pdb, None, &Definitions::new()).is_err());
+        assert_eq!(select(&pdb, "name re:\"^ca$\" and resid 1"), vec![4]);
+
+        // Primes are part of nucleic acid atom names
+        let pdb = test_pdb("tests/test_nucleic.pdb");
+        assert_eq!(select(&pdb, "name O5'"), vec![4]);
+        assert_eq!(select(&pdb, "name c1',o4'"), vec![7, 11]);
+        assert_eq!(select(&pdb, "name C?'"), vec![5, 6, 8, 10, 11]);
+    }
+
+    #[test]
+    fn bond_selection_test() {
+        let pdb = test_pdb("tests/test_blank.pdb");
+
+        assert_eq!(select(&pdb, "bondedto id 4"), vec![1, 5, 6, 17]);
+        assert_eq!(select(&pdb, "nbonds 1 from id 4"), vec![1, 4, 5, 6, 17]);
+        assert_eq!(select(&pdb, "fragment id 78"), vec![78, 79, 80]);
+        assert_eq!(
+            select(&pdb, "fragment (resid 1)"),
+            (1..=77).collect::<Vec<usize>>()
+        );
+        assert_eq!(select(&pdb, "bondedto resid 1 and not water"), vec![19]);
+        assert_eq!(
+            get_atomlist_from_input(
+                "bondedto id 4",
+                &pdb,
+                Some(Partial::Sidechain),
+                &Definitions::new()
+            )
+            .unwrap(),
+            vec![6]
+        );
+    }
+
+    #[test]
+    fn nearest_selection_test() {
+        let pdb = test_pdb("tests/test_blank.pdb");
+
+        let mut sphere = get_atom_sphere(&pdb, 26, 2.0, false).unwrap();
+        sphere.sort_unstable();
+        assert_eq!(select(&pdb, "nearest 3 atoms to id 26"), sphere);
+        assert_eq!(
+            select(&pdb, "nearest 1 res to resid 2"),
+            select(&pdb, "resid 1")
+        );
+        // The residue of a partially selected origin is not among the nearest residues
+        assert_eq!(
+            select(&pdb, "nearest 2 residues to id 81"),
+            select(&pdb, "resid 3,6")
+        );
+        assert_eq!(
+            select(&pdb, "nearest 1 res to (resid 2 and name CA)"),
+            select(&pdb, "resid 3")
+        );
+        assert_eq!(
+            select(&pdb, "nearest 3 atoms to resid 1-5"),
+            vec![78, 79, 80]
+        );
+        assert!(select(&pdb, "nearest 0 atoms to id 1").is_empty());
+    }
+
+    #[test]
+    fn metalsite_selection_test() {
+        let pdb = test_pdb("tests/test_metal.pdb");
+
+        assert_eq!(
+            select(&pdb, "metalsite element zn"),
+            (1..=8).collect::<Vec<usize>>()
+        );
+        // Only the sidechain of the cysteine but the whole waters are selected
+        assert_eq!(
+            select_partial(&pdb, "metalsite element zn", Some(Partial::Sidechain)),
+            vec![3, 4, 5, 6, 7, 8]
+        );
+        assert_eq!(
+            select(&pdb, "metalsite resn cu"),
+            (9..=13).collect::<Vec<usize>>()
+        );
+        assert!(select(&pdb, "metalsite resn wat").is_empty());
+    }
+
+    #[test]
+    fn secondary_structure_selection_test() {
+        let pdb = test_pdb("tests/test_insert.pdb");
+
+        let helix = select(&pdb, "ss helix and name CA");
+        assert!(helix.contains(&1738) && helix.contains(&1826));
+        assert!(!helix.contains(&2357));
+        assert_eq!(
+            select(&pdb, "SS helix or ss strand or ss turn or ss coil"),
+            select(&pdb, "protein")
+        );
+        assert_eq!(
+            select_partial(&pdb, "ss strand", Some(Partial::Backbone)),
+            select(&pdb, "ss strand and backbone")
+        );
+    }
+
     // #[test]
     // fn get_residuelist_test() {
     //     let pdb = test_pdb("tests/test_get_residuelist.pdb");
@@ -0,0 +1,251 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{BondGraph, Layer};
+use crate::residue_names::is_protein;
+
+use colored::Colorize;
+use comfy_table::modifiers::{UTF8_ROUND_CORNERS, UTF8_SOLID_INNER_BORDERS};
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{Row, Table};
+use pdbtbx::{
+    Atom, AtomConformerResidueChainModel, ContainsAtomConformer, ContainsAtomConformerResidue, PDB,
+};
+
+/// Maximum distance in A between donor and acceptor.
+const MAX_DONOR_ACCEPTOR_DISTANCE: f64 = 3.5;
+/// Maximum distance in A between hydrogen and acceptor.
+const MAX_HYDROGEN_ACCEPTOR_DISTANCE: f64 = 2.5;
+/// Minimum donor-hydrogen-acceptor angle in degrees.
+const MIN_HYDROGEN_ANGLE: f64 = 120.0;
+/// Minimum angle in degrees between the acceptor and any heavy atom bonded to the donor,
+/// used if no hydrogens are present.
+const MIN_HEAVY_ATOM_ANGLE: f64 = 90.0;
+
+/// Hydrogen bond given by atom IDs. The hydrogen is only known if the structure contains
+/// hydrogens, in which case the angle is the donor-hydrogen-acceptor angle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HBond {
+    pub donor: usize,
+    pub hydrogen: Option<usize>,
+    pub acceptor: usize,
+    pub distance: f64,
+    pub angle: Option<f64>,
+}
+
+fn is_hydrogen(atom: &Atom) -> bool {
+    atom.element().eq_ignore_ascii_case("H") || atom.element().eq_ignore_ascii_case("D")
+}
+
+fn is_polar(atom: &Atom) -> bool {
+    atom.element().eq_ignore_ascii_case("N") || atom.element().eq_ignore_ascii_case("O")
+}
+
+/// Finds hydrogen bonds between nitrogen and oxygen atoms of which at least one is part of
+/// the given atoms. If the structure contains hydrogens, donors need a bonded hydrogen that
+/// fulfills the distance and angle criteria and nitrogens with three or more bonds are no
+/// acceptors. Otherwise, the heavy atoms bonded to the donor must not point towards the
+/// acceptor and backbone nitrogens and oxygens are only donors and acceptors, respectively.
+/// Atoms up to three bonds apart are not considered.
+pub fn find_hbonds(pdb: &PDB, atoms: Option<&[usize]>) -> Vec<HBond> {
+    let graph = BondGraph::new(pdb);
+    let atom_set: Option<HashSet<&usize>> = atoms.map(|l| l.iter().collect());
+    let atom_map: HashMap<usize, AtomConformerResidueChainModel> = pdb
+        .atoms_with_hierarchy()
+        .map(|a| (a.atom().serial_number(), a))
+        .collect();
+    let explicit_hydrogens = pdb.atoms().any(is_hydrogen);
+
+    let backbone = |a: &AtomConformerResidueChainModel, name: &str| {
+        is_protein(a.residue().name().unwrap_or("")) && a.atom().name() == name
+    };
+    let is_donor = |a: &AtomConformerResidueChainModel| {
+        is_polar(a.atom())
+            && !(backbone(a, "O")
+                || backbone(a, "N") && a.residue().name() == Some("PRO") && !explicit_hydrogens)
+    };
+    let is_acceptor = |a: &AtomConformerResidueChainModel| {
+        is_polar(a.atom())
+            && if explicit_hydrogens {
+                !(a.atom().element().eq_ignore_ascii_case("N")
+                    && graph.neighbors(a.atom().serial_number()).len() >= 3)
+            } else {
+                !backbone(a, "N")
+            }
+    };
+
+    let tree = pdb.create_atom_rtree();
+    let mut hbonds = Vec::new();
+
+    for donor in pdb.atoms_with_hierarchy().filter(is_donor) {
+        let d = donor.atom();
+        let neighbors: Vec<&Atom> = graph
+            .neighbors(d.serial_number())
+            .iter()
+            .filter_map(|id| atom_map.get(id).map(|a| a.atom()))
+            .collect();
+        let hydrogens: Vec<&Atom> = neighbors
+            .iter()
+            .copied()
+            .filter(|a| is_hydrogen(a))
+            .collect();
+        if explicit_hydrogens && hydrogens.is_empty() {
+            continue;
+        }
+        let nearby = graph.within_bonds(&[d.serial_number()], 3);
+
+        for acceptor in tree.locate_within_distance(d.pos(), MAX_DONOR_ACCEPTOR_DISTANCE.powi(2)) {
+            let id = acceptor.serial_number();
+            if nearby.contains(&id) || !matches!(atom_map.get(&id), Some(a) if is_acceptor(a)) {
+                continue;
+            }
+            if let Some(set) = &atom_set {
+                if !set.contains(&d.serial_number()) && !set.contains(&id) {
+                    continue;
+                }
+            }
+
+            let (hydrogen, angle) = if explicit_hydrogens {
+                // The hydrogen with the most linear arrangement is taken
+                let best = hydrogens
+                    .iter()
+                    .filter(|h| h.distance(acceptor) <= MAX_HYDROGEN_ACCEPTOR_DISTANCE)
+                    .map(|h| (h, d.angle(h, acceptor)))
+                    .filter(|(_, angle)| *angle >= MIN_HYDROGEN_ANGLE)
+                    .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+                match best {
+                    Some((h, angle)) => (Some(h.serial_number()), Some(angle)),
+                    None => continue,
+                }
+            } else {
+                if neighbors
+                    .iter()
+                    .any(|x| x.angle(d, acceptor) < MIN_HEAVY_ATOM_ANGLE)
+                {
+                    continue;
+                }
+                (None, None)
+            };
+
+            hbonds.push(HBond {
+                donor: d.serial_number(),
+                hydrogen,
+                acceptor: id,
+                distance: d.distance(acceptor),
+                angle,
+            })
+        }
+    }
+
+    hbonds.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap());
+    hbonds
+}
+
+/// Lists the hydrogen bonds involving any of the given atoms or all hydrogen bonds. Bonds
+/// between different layers are listed first since they indicate residues that might have to
+/// be moved to the QM region.
+pub fn analyze_hbonds(pdb: &PDB, atoms: Option<&[usize]>) -> Result<Table, anyhow::Error> {
+    let hbonds = find_hbonds(pdb, atoms);
+    ensure!(!hbonds.is_empty(), "No hydrogen bonds found!");
+
+    let atom_map: HashMap<usize, AtomConformerResidueChainModel> = pdb
+        .atoms_with_hierarchy()
+        .map(|a| (a.atom().serial_number(), a))
+        .collect();
+    let residue = |atom_hier: &AtomConformerResidueChainModel| {
+        format!(
+            "{} {}{}",
+            atom_hier.residue().name().unwrap_or(""),
+            atom_hier.residue().serial_number(),
+            atom_hier.residue().insertion_code().unwrap_or("")
+        )
+    };
+    let layers = |hbond: &HBond| {
+        (
+            Layer::of(atom_map[&hbond.donor].atom()),
+            Layer::of(atom_map[&hbond.acceptor].atom()),
+        )
+    };
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+    table.set_header(Row::from(vec![
+        "Donor ID",
+        "Donor",
+        "Donor Residue",
+        "Acceptor ID",
+        "Acceptor",
+        "Acceptor Residue",
+        "Distance",
+        "Angle",
+        "Layers",
+    ]));
+
+    let (crossing, internal): (Vec<&HBond>, Vec<&HBond>) = hbonds.iter().partition(|h| {
+        let (d, a) = layers(h);
+        d != a
+    });
+    for hbond in crossing.into_iter().chain(internal) {
+        let (donor, acceptor) = (&atom_map[&hbond.donor], &atom_map[&hbond.acceptor]);
+        let (donor_layer, acceptor_layer) = layers(hbond);
+        let layer_str = format!("{}-{}", donor_layer, acceptor_layer);
+
+        table.add_row(Row::from(vec![
+            hbond.donor.to_string(),
+            donor.atom().name().to_owned(),
+            residue(donor),
+            hbond.acceptor.to_string(),
+            acceptor.atom().name().to_owned(),
+            residue(acceptor),
+            format!("{:.2}", hbond.distance),
+            hbond.angle.map_or("-".to_owned(), |a| format!("{:.1}", a)),
+            if donor_layer != acceptor_layer {
+                layer_str.yellow().to_string()
+            } else {
+                layer_str
+            },
+        ]));
+    }
+
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdbtbx::StrictnessLevel;
+
+    fn test_pdb(path: &str) -> PDB {
+        let (pdb, _) = pdbtbx::open_pdb(path, StrictnessLevel::Strict).unwrap();
+        pdb
+    }
+
+    #[test]
+    fn hbonds_test() {
+        // Only the first water points a hydrogen at the second one
+        let pdb = test_pdb("tests/test_hbonds.pdb");
+        let hbonds = find_hbonds(&pdb, None);
+        assert_eq!(hbonds.len(), 1);
+        assert_eq!(
+            (hbonds[0].donor, hbonds[0].hydrogen, hbonds[0].acceptor),
+            (1, Some(2), 4)
+        );
+        assert!((hbonds[0].distance - 2.85).abs() < 1e-3);
+        assert!((hbonds[0].angle.unwrap() - 180.0).abs() < 1e-3);
+        assert_eq!(find_hbonds(&pdb, Some(&[4])), hbonds);
+        assert!(find_hbonds(&pdb, Some(&[7, 8, 9])).is_empty());
+        assert!(analyze_hbonds(&pdb, Some(&[7])).is_err());
+
+        // Without hydrogens, the backbone oxygen only accepts and the backbone nitrogen only
+        // donates to the water on the opposite side of its alpha carbon
+        let pdb = test_pdb("tests/test_hbonds_heavy.pdb");
+        let pairs: Vec<(usize, usize)> = find_hbonds(&pdb, None)
+            .iter()
+            .map(|h| (h.donor, h.acceptor))
+            .collect();
+        assert_eq!(pairs, vec![(5, 4), (1, 6)]);
+        assert!(find_hbonds(&pdb, None).iter().all(|h| h.hydrogen.is_none()));
+    }
+}
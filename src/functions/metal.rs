@@ -0,0 +1,259 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::elements::is_metal;
+
+use comfy_table::modifiers::{UTF8_ROUND_CORNERS, UTF8_SOLID_INNER_BORDERS};
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{Row, Table};
+use itertools::Itertools;
+use pdbtbx::{
+    Atom, AtomConformerResidueChainModel, ContainsAtomConformer, ContainsAtomConformerResidue, PDB,
+};
+
+/// Largest distance in A any ligand can have to a metal.
+const MAX_COORDINATION_DISTANCE: f64 = 3.7;
+
+/// Maximum distance in A between a metal and a first-shell ligand atom of the given elements,
+/// based on typical bond lengths in metalloproteins with some tolerance. Returns None for
+/// elements that are not considered as ligating atoms.
+fn coordination_cutoff(metal: &str, ligand: &str) -> Option<f64> {
+    let base = match metal.to_uppercase().as_str() {
+        "NA" | "HG" => 2.9,
+        "K" => 3.2,
+        "MG" => 2.5,
+        "CA" | "CD" => 2.8,
+        _ => 2.6,
+    };
+    let offset = match ligand.to_uppercase().as_str() {
+        "N" | "O" => 0.0,
+        "S" | "CL" => 0.4,
+        "SE" | "BR" => 0.5,
+        _ => return None,
+    };
+    Some(base + offset)
+}
+
+/// Coordination geometries recognized from the ligand-metal-ligand angles.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Geometry {
+    Linear,
+    Bent,
+    TrigonalPlanar,
+    TrigonalPyramidal,
+    Tetrahedral,
+    Seesaw,
+    SquarePlanar,
+    TrigonalBipyramidal,
+    SquarePyramidal,
+    Octahedral,
+    TrigonalPrismatic,
+    Irregular,
+}
+
+impl fmt::Display for Geometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Geometry::Linear => "Linear",
+                Geometry::Bent => "Bent",
+                Geometry::TrigonalPlanar => "Trigonal planar",
+                Geometry::TrigonalPyramidal => "Trigonal pyramidal",
+                Geometry::Tetrahedral => "Tetrahedral",
+                Geometry::Seesaw => "Seesaw",
+                Geometry::SquarePlanar => "Square planar",
+                Geometry::TrigonalBipyramidal => "Trigonal bipyramidal",
+                Geometry::SquarePyramidal => "Square pyramidal",
+                Geometry::Octahedral => "Octahedral",
+                Geometry::TrigonalPrismatic => "Trigonal prismatic",
+                Geometry::Irregular => "Irregular",
+            }
+        )
+    }
+}
+
+/// Angle in degrees between the bonds of the metal to two ligands.
+fn ligand_angle(metal: &Atom, a: &Atom, b: &Atom) -> f64 {
+    let u = (a.x() - metal.x(), a.y() - metal.y(), a.z() - metal.z());
+    let v = (b.x() - metal.x(), b.y() - metal.y(), b.z() - metal.z());
+    let dot = u.0 * v.0 + u.1 * v.1 + u.2 * v.2;
+    (dot / (metal.distance(a) * metal.distance(b)))
+        .clamp(-1.0, 1.0)
+        .acos()
+        .to_degrees()
+}
+
+/// Classifies the coordination geometry by the ligand-metal-ligand angles. Four- and
+/// five-coordinate sites are told apart by the geometry indices tau4 (Yang et al., Dalton
+/// Trans. 2007) and tau5 (Addison et al., Dalton Trans. 1984).
+fn classify_geometry(metal: &Atom, ligands: &[&Atom]) -> Option<Geometry> {
+    let angles: Vec<f64> = ligands
+        .iter()
+        .tuple_combinations()
+        .map(|(a, b)| ligand_angle(metal, a, b))
+        .sorted_by(|a, b| b.partial_cmp(a).unwrap())
+        .collect();
+
+    let geometry = match ligands.len() {
+        0 | 1 => return None,
+        2 if angles[0] >= 160.0 => Geometry::Linear,
+        2 => Geometry::Bent,
+        3 if angles.iter().sum::<f64>() >= 350.0 => Geometry::TrigonalPlanar,
+        3 => Geometry::TrigonalPyramidal,
+        4 => match (360.0 - angles[0] - angles[1]) / 141.0 {
+            tau4 if tau4 < 0.3 => Geometry::SquarePlanar,
+            tau4 if tau4 < 0.6 => Geometry::Seesaw,
+            _ => Geometry::Tetrahedral,
+        },
+        5 if (angles[0] - angles[1]) / 60.0 >= 0.5 => Geometry::TrigonalBipyramidal,
+        5 => Geometry::SquarePyramidal,
+        6 if angles.iter().filter(|a| **a >= 150.0).count() >= 2 => Geometry::Octahedral,
+        6 => Geometry::TrigonalPrismatic,
+        _ => Geometry::Irregular,
+    };
+    Some(geometry)
+}
+
+/// Metal atom with its first coordination shell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetalSite {
+    pub metal: usize,
+    /// Ligating atoms with their distances to the metal, sorted by distance.
+    pub ligands: Vec<(usize, f64)>,
+    pub geometry: Option<Geometry>,
+}
+
+/// Finds the first-shell ligands of all metal atoms among the given atoms. Ligating atoms are
+/// nitrogen, oxygen, sulfur, selenium and halogens within element-specific distance cutoffs.
+pub fn find_metal_sites(pdb: &PDB, atoms: &[usize]) -> Vec<MetalSite> {
+    let atom_set: HashSet<&usize> = atoms.iter().collect();
+    let tree = pdb.create_atom_rtree();
+
+    pdb.atoms()
+        .filter(|a| atom_set.contains(&a.serial_number()) && is_metal(a.element()))
+        .map(|metal| {
+            let ligand_atoms: Vec<&Atom> = tree
+                .locate_within_distance(metal.pos(), MAX_COORDINATION_DISTANCE.powi(2))
+                .copied()
+                .filter(|a| {
+                    matches!(
+                        coordination_cutoff(metal.element(), a.element()),
+                        Some(cutoff) if metal.distance(a) <= cutoff
+                    )
+                })
+                .sorted_by(|a, b| {
+                    metal
+                        .distance(a)
+                        .partial_cmp(&metal.distance(b))
+                        .unwrap()
+                        .then(a.serial_number().cmp(&b.serial_number()))
+                })
+                .collect();
+
+            MetalSite {
+                metal: metal.serial_number(),
+                ligands: ligand_atoms
+                    .iter()
+                    .map(|a| (a.serial_number(), metal.distance(a)))
+                    .collect(),
+                geometry: classify_geometry(metal, &ligand_atoms),
+            }
+        })
+        .collect()
+}
+
+/// Lists the metal sites among the given atoms with coordination number, geometry and ligands.
+pub fn analyze_metalsite(pdb: &PDB, atoms: &[usize]) -> Result<Table, anyhow::Error> {
+    let sites = find_metal_sites(pdb, atoms);
+    ensure!(!sites.is_empty(), "No metal atoms found in selection!");
+
+    let atoms: HashMap<usize, AtomConformerResidueChainModel> = pdb
+        .atoms_with_hierarchy()
+        .map(|a| (a.atom().serial_number(), a))
+        .collect();
+    let residue = |atom_hier: &AtomConformerResidueChainModel| {
+        format!(
+            "{} {}{}",
+            atom_hier.residue().name().unwrap_or(""),
+            atom_hier.residue().serial_number(),
+            atom_hier.residue().insertion_code().unwrap_or("")
+        )
+    };
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+    table.set_header(Row::from(vec![
+        "Metal ID",
+        "Metal",
+        "Residue",
+        "CN",
+        "Geometry",
+        "Ligand ID",
+        "Ligand",
+        "Ligand Residue",
+        "Distance",
+    ]));
+
+    for site in sites {
+        let metal = &atoms[&site.metal];
+        let ligands = site.ligands.iter().map(|(id, _)| &atoms[id]);
+
+        table.add_row(Row::from(vec![
+            site.metal.to_string(),
+            metal.atom().name().to_owned(),
+            residue(metal),
+            site.ligands.len().to_string(),
+            site.geometry.map_or("-".to_owned(), |g| g.to_string()),
+            site.ligands.iter().map(|(id, _)| id).join("\n"),
+            ligands.clone().map(|a| a.atom().name()).join("\n"),
+            ligands.map(residue).join("\n"),
+            site.ligands
+                .iter()
+                .map(|(_, d)| format!("{:.3}", d))
+                .join("\n"),
+        ]));
+    }
+
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdbtbx::StrictnessLevel;
+
+    fn test_pdb(path: &str) -> PDB {
+        let (pdb, _) = pdbtbx::open_pdb(path, StrictnessLevel::Strict).unwrap();
+        pdb
+    }
+
+    #[test]
+    fn metal_site_test() {
+        let pdb = test_pdb("tests/test_metal.pdb");
+        let sites = find_metal_sites(&pdb, &(1..=14).collect::<Vec<usize>>());
+        assert_eq!(sites.len(), 2);
+
+        // Zinc bound to a cysteine and three waters
+        let zn = &sites[0];
+        assert_eq!(zn.metal, 5);
+        assert_eq!(
+            zn.ligands.iter().map(|(id, _)| *id).collect::<Vec<usize>>(),
+            vec![6, 7, 8, 4]
+        );
+        assert!((zn.ligands[3].1 - 2.3).abs() < 1e-3);
+        assert_eq!(zn.geometry, Some(Geometry::Tetrahedral));
+
+        // The axial water of the copper ion is too far away
+        let cu = &sites[1];
+        assert_eq!(cu.ligands.len(), 4);
+        assert_eq!(cu.geometry, Some(Geometry::SquarePlanar));
+
+        assert_eq!(find_metal_sites(&pdb, &[9]), vec![cu.clone()]);
+        assert!(analyze_metalsite(&pdb, &[1, 2, 3]).is_err());
+    }
+}
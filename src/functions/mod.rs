@@ -1,13 +1,33 @@
 mod analyze;
+mod bonds;
+mod check;
+mod completeness;
 mod edit;
+mod environment;
 mod get;
+mod hbonds;
+mod metal;
 mod output;
 mod parse;
 mod query;
+mod sasa;
+mod secondary;
+mod series;
+mod topology;
 
 pub use analyze::*;
+pub use bonds::*;
+pub use check::*;
+pub use completeness::*;
 pub use edit::*;
+pub use environment::*;
 pub use get::*;
+pub use hbonds::*;
+pub use metal::*;
 pub use output::*;
 pub use parse::*;
 pub use query::*;
+pub use sasa::*;
+pub use secondary::*;
+pub use series::*;
+pub use topology::*;
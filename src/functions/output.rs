@@ -1,11 +1,20 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
 use std::io;
 use std::io::prelude::Write;
+use std::path::Path;
 
 use anyhow::{Context, Result};
+use itertools::Itertools;
 use pdbtbx::{
-    ContainsAtomConformer, ContainsAtomConformerResidue, ContainsAtomConformerResidueChain, PDB,
+    Atom, ContainsAtomConformer, ContainsAtomConformerResidue, ContainsAtomConformerResidueChain,
+    PDB,
 };
 
+use super::{formal_charges, get_atomlist, get_region_charge, BondGraph, Layer};
+use crate::elements::is_metal;
+use crate::options::Region;
+
 /// Prints all Atoms in Molecule to stdout in PDB file format
 pub fn print_pdb_to_stdout(pdb: &PDB) -> Result<(), anyhow::Error> {
     let stdout = io::stdout();
@@ -29,3 +38,309 @@ pub fn print_pdb_to_stdout(pdb: &PDB) -> Result<(), anyhow::Error> {
     }
     Ok(())
 }
+
+/// Notation of atom lists written by pdbman.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum IdNotation {
+    /// 0-based positions in ORCA's compact notation, e.g. '{0:22 482:496}'.
+    Orca,
+    /// 1-based PDB serial numbers in the range notation of the selection syntax, e.g. '1-5,9'.
+    Serial,
+}
+
+/// Formats a list of atom indices or IDs in the given notation, giving consecutive ones as
+/// ranges.
+pub fn format_id_ranges(ids: &[usize], notation: IdNotation) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &i in ids.iter().sorted() {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == i => *end = i,
+            _ => ranges.push((i, i)),
+        }
+    }
+
+    let (range_sep, list_sep) = match notation {
+        IdNotation::Orca => (":", " "),
+        IdNotation::Serial => ("-", ","),
+    };
+    let ranges = ranges
+        .into_iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}{}{}", start, range_sep, end)
+            }
+        })
+        .join(list_sep);
+    match notation {
+        IdNotation::Orca => format!("{{{}}}", ranges),
+        IdNotation::Serial => ranges,
+    }
+}
+
+/// Creates the input for an ORCA QM/MM calculation from the current QM1, QM2 and active regions.
+/// ORCA refers to atoms by their 0-based position in the PDB file instead of their ID.
+pub fn get_orca_input(pdb: &PDB, pdb_path: &str) -> Result<String, anyhow::Error> {
+    let stem = Path::new(pdb_path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(pdb_path);
+    get_orca_input_with_forcefield(pdb, pdb_path, &format!("{}.ORCAFF.prms", stem))
+}
+
+/// Creates the input for an ORCA QM/MM calculation like 'get_orca_input' but with the given
+/// force field file, e.g. for structures derived from the one the force field was created for.
+pub fn get_orca_input_with_forcefield(
+    pdb: &PDB,
+    pdb_path: &str,
+    forcefield_path: &str,
+) -> Result<String, anyhow::Error> {
+    let indices: HashMap<usize, usize> = pdb
+        .atoms()
+        .map(Atom::serial_number)
+        .enumerate()
+        .map(|(i, serial)| (serial, i))
+        .collect();
+    let to_indices = |list: Vec<usize>| -> Vec<usize> { list.iter().map(|s| indices[s]).collect() };
+
+    let qm1 = get_atomlist(pdb, Region::QM1).context("No atoms in QM1 region")?;
+    let pdb_name = Path::new(pdb_path)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or(pdb_path);
+
+    let mut input = String::from("%qmmm\n");
+    writeln!(input, "  ORCAFFFilename \"{}\"", forcefield_path)?;
+    writeln!(
+        input,
+        "  QMAtoms {} end",
+        format_id_ranges(&to_indices(qm1), IdNotation::Orca)
+    )?;
+    if let Ok(qm2) = get_atomlist(pdb, Region::QM2) {
+        writeln!(
+            input,
+            "  QM2Atoms {} end",
+            format_id_ranges(&to_indices(qm2), IdNotation::Orca)
+        )?;
+    }
+    if let Ok(active) = get_atomlist(pdb, Region::Active) {
+        writeln!(
+            input,
+            "  ActiveAtoms {} end",
+            format_id_ranges(&to_indices(active), IdNotation::Orca)
+        )?;
+    }
+    writeln!(
+        input,
+        "  Charge_Total 0   # placeholder, set the charge of the whole system"
+    )?;
+    writeln!(
+        input,
+        "  Mult_Total 1     # placeholder, set the multiplicity of the whole system"
+    )?;
+    writeln!(input, "end\n")?;
+    writeln!(
+        input,
+        "*pdbfile 0 1 {}   # placeholder, set the charge and multiplicity of the QM region",
+        pdb_name
+    )?;
+
+    Ok(input)
+}
+
+/// Length in A of the bond between a hydrogen cap and the given element.
+fn cap_bond_length(element: &str) -> f64 {
+    match element.to_uppercase().as_str() {
+        "N" => 1.01,
+        "O" => 0.96,
+        "S" => 1.34,
+        _ => 1.09,
+    }
+}
+
+/// Creates a cluster model of the QM1 (and QM2) atoms in XYZ format. If 'cap' is set, the
+/// bonds to outer atoms are saturated by hydrogens placed along the original bond vectors.
+/// Bonds to metals are coordinate bonds and not capped. The comment line contains the
+/// estimated charge as well as the IDs of the atoms and of the outer atoms replaced by caps,
+/// in the order of the file.
+pub fn get_xyz(pdb: &PDB, include_qm2: bool, cap: bool) -> Result<String, anyhow::Error> {
+    let layers = if include_qm2 {
+        vec![Layer::QM1, Layer::QM2]
+    } else {
+        vec![Layer::QM1]
+    };
+    let atoms: HashMap<usize, &Atom> = pdb.atoms().map(|a| (a.serial_number(), a)).collect();
+    let cluster: Vec<&Atom> = pdb
+        .atoms()
+        .filter(|a| layers.contains(&Layer::of(a)))
+        .collect();
+    ensure!(!cluster.is_empty(), "No atoms in QM1 region");
+
+    let graph = BondGraph::new(pdb);
+    let region_charge = get_region_charge(pdb, &formal_charges(pdb), &graph, &layers)?;
+
+    // Pairs of inner and outer atoms of cut bonds
+    let cuts: Vec<(&Atom, &Atom)> = if cap {
+        cluster
+            .iter()
+            .flat_map(|inner| {
+                graph
+                    .neighbors(inner.serial_number())
+                    .iter()
+                    .map(|n| atoms[n])
+                    .filter(|outer| !layers.contains(&Layer::of(outer)))
+                    .filter(|outer| !is_metal(inner.element()) && !is_metal(outer.element()))
+                    .map(move |outer| (*inner, outer))
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let format_element = |element: &str| {
+        let mut chars = element.trim().chars();
+        match chars.next() {
+            Some(c) => c
+                .to_uppercase()
+                .chain(chars.flat_map(char::to_lowercase))
+                .collect(),
+            None => String::from("X"),
+        }
+    };
+
+    let mut xyz = String::new();
+    writeln!(xyz, "{}", cluster.len() + cuts.len())?;
+    write!(
+        xyz,
+        "{} cluster, charge {}, atoms {}",
+        layers.iter().join("+"),
+        region_charge.charge,
+        format_id_ranges(
+            &cluster
+                .iter()
+                .map(|a| a.serial_number())
+                .collect::<Vec<_>>(),
+            IdNotation::Serial
+        )
+    )?;
+    if !cuts.is_empty() {
+        write!(
+            xyz,
+            ", caps replace {}",
+            cuts.iter()
+                .map(|(_, outer)| outer.serial_number())
+                .join(" ")
+        )?;
+    }
+    writeln!(xyz)?;
+
+    for atom in &cluster {
+        writeln!(
+            xyz,
+            "{:<2} {:>12.6} {:>12.6} {:>12.6}",
+            format_element(atom.element()),
+            atom.x(),
+            atom.y(),
+            atom.z()
+        )?;
+    }
+    for (inner, outer) in cuts {
+        let vector = [
+            outer.x() - inner.x(),
+            outer.y() - inner.y(),
+            outer.z() - inner.z(),
+        ];
+        let scale = cap_bond_length(inner.element()) / inner.distance(outer);
+        writeln!(
+            xyz,
+            "{:<2} {:>12.6} {:>12.6} {:>12.6}",
+            "H",
+            inner.x() + vector[0] * scale,
+            inner.y() + vector[1] * scale,
+            inner.z() + vector[2] * scale
+        )?;
+    }
+
+    Ok(xyz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions::edit_atoms_unchecked;
+    use pdbtbx::StrictnessLevel;
+
+    fn test_pdb(path: &str) -> PDB {
+        let (pdb, _) = pdbtbx::open_pdb(path, StrictnessLevel::Strict).unwrap();
+        pdb
+    }
+
+    #[test]
+    fn id_ranges_test() {
+        let ids = [4, 0, 1, 2, 7, 8, 10];
+        assert_eq!(format_id_ranges(&ids, IdNotation::Orca), "{0:2 4 7:8 10}");
+        assert_eq!(format_id_ranges(&[3], IdNotation::Orca), "{3}");
+        assert_eq!(format_id_ranges(&ids, IdNotation::Serial), "0-2,4,7-8,10");
+        assert_eq!(format_id_ranges(&[3], IdNotation::Serial), "3");
+    }
+
+    #[test]
+    fn orca_input_test() {
+        let pdb = test_pdb("tests/test_get_atomlist.pdb");
+        let input = get_orca_input(&pdb, "tests/test_get_atomlist.pdb").unwrap();
+        let lines: Vec<&str> = input.lines().collect();
+
+        assert_eq!(lines[0], "%qmmm");
+        assert_eq!(
+            lines[1],
+            "  ORCAFFFilename \"test_get_atomlist.ORCAFF.prms\""
+        );
+        assert_eq!(lines[2], "  QMAtoms {0:1 3:5} end");
+        assert_eq!(lines[3], "  QM2Atoms {7:8 10:11} end");
+        assert_eq!(lines[4], "  ActiveAtoms {0:2 4:5 7:8} end");
+        assert!(lines
+            .last()
+            .unwrap()
+            .starts_with("*pdbfile 0 1 test_get_atomlist.pdb"));
+        assert!(get_orca_input(&test_pdb("tests/test_blank.pdb"), "test_blank.pdb").is_err());
+    }
+
+    #[test]
+    fn xyz_test() {
+        let pdb = test_pdb("tests/test_get_atomlist.pdb");
+
+        let xyz = get_xyz(&pdb, false, false).unwrap();
+        let lines: Vec<&str> = xyz.lines().collect();
+        assert_eq!(lines[0], "5");
+        assert_eq!(lines[1], "QM1 cluster, charge 1, atoms 1-2,4-6");
+        assert_eq!(lines.len(), 7);
+
+        let xyz = get_xyz(&pdb, false, true).unwrap();
+        let lines: Vec<&str> = xyz.lines().collect();
+        assert_eq!(lines[0], "10");
+        assert!(lines[1].ends_with("caps replace 3 17 7 8 9"));
+        assert_eq!(lines[2], "N     66.397000    49.061000    85.017000");
+
+        // Cap replacing atom 17 (C) bonded to atom 4 (CA) at 1.09 A
+        let cap: Vec<f64> = lines[8]
+            .split_whitespace()
+            .skip(1)
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let ca = [66.603, 49.087, 86.441];
+        let distance = (0..3).map(|i| (cap[i] - ca[i]).powi(2)).sum::<f64>().sqrt();
+        assert!((distance - 1.09).abs() < 1e-5);
+
+        let xyz = get_xyz(&pdb, true, true).unwrap();
+        assert!(xyz.lines().nth(1).unwrap().starts_with("QM1+QM2 cluster"));
+        assert!(get_xyz(&test_pdb("tests/test_blank.pdb"), false, true).is_err());
+
+        // The water oxygen coordinating the copper ion is not capped
+        let mut pdb = test_pdb("tests/test_bonds.pdb");
+        edit_atoms_unchecked(&mut pdb, &[1, 2, 3], "Add", Region::QM1);
+        let xyz = get_xyz(&pdb, false, true).unwrap();
+        assert_eq!(xyz.lines().next(), Some("3"));
+        assert!(!xyz.contains("caps"));
+    }
+}
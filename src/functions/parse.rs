@@ -1,64 +1,144 @@
-use std::borrow::ToOwned;
 use std::collections::HashSet;
 
 use anyhow::Result;
 use itertools::Itertools;
-use pdbtbx::{Atom, Residue, PDB};
+use lazy_regex::{Regex, RegexBuilder};
+use pdbtbx::{Atom, PDB};
 use rayon::iter::FromParallelIterator;
 use rayon::prelude::ParallelIterator;
 
 type AtomList = Vec<usize>;
-type ResidueList = Vec<isize>;
+/// Residues given by chain ID, serial number and insertion code.
+pub type ResidueList<'a> = Vec<(&'a str, isize, Option<&'a str>)>;
+
+/// A name given in a selection. Quoted names prefixed with 're:' are regular expressions, names
+/// containing '*' or '?' are glob patterns and all other names are plain names. All of them are
+/// compared case-insensitively.
+enum NamePattern {
+    Exact(String),
+    Pattern(Regex),
+}
 
-/// Takes a comma-separated list (usually from command line input) as string and parses it into
-/// a vector of Atom IDs. The Input may be atom IDs or Atom Names
-pub fn parse_atomic_list(input: &str, pdb: &PDB) -> Result<AtomList, anyhow::Error> {
-    let input_vec: Vec<&str> = input.split(',').collect();
-    let input_set: HashSet<String> = input_vec.iter().map(|s| s.to_lowercase()).collect();
+impl NamePattern {
+    fn new(input: &str) -> Result<Self, anyhow::Error> {
+        if let Some(pattern) = input
+            .strip_prefix("re:\"")
+            .and_then(|p| p.strip_suffix('"'))
+        {
+            let regex = RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| anyhow!("Invalid regular expression '{}': {}", input, e))?;
+            Ok(NamePattern::Pattern(regex))
+        } else if input.contains(['*', '?']) {
+            let mut glob = String::from("^");
+            for c in input.chars() {
+                match c {
+                    '*' => glob.push_str(".*"),
+                    '?' => glob.push('.'),
+                    '.' | '+' | '(' | ')' | '|' | '[' | ']' | '{' | '}' | '^' | '$' | '\\' => {
+                        glob.push('\\');
+                        glob.push(c)
+                    }
+                    _ => glob.push(c),
+                }
+            }
+            glob.push('$');
+            let regex = RegexBuilder::new(&glob)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| anyhow!("Invalid pattern '{}': {}", input, e))?;
+            Ok(NamePattern::Pattern(regex))
+        } else {
+            Ok(NamePattern::Exact(input.to_lowercase()))
+        }
+    }
 
-    let pdb_set: HashSet<String> =
-        HashSet::from_par_iter(pdb.par_atoms().map(|a| a.name().to_lowercase()));
-    let mut missing_atoms = input_set.difference(&pdb_set).peekable();
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            NamePattern::Exact(s) => name.to_lowercase() == *s,
+            NamePattern::Pattern(r) => r.is_match(name),
+        }
+    }
+}
+
+/// Compiles the given names to patterns and makes sure that each of them matches at least one
+/// of the names present in the structure.
+fn compile_name_patterns(
+    input: &[&str],
+    pdb_names: &HashSet<&str>,
+    kind: &str,
+) -> Result<Vec<NamePattern>, anyhow::Error> {
+    let patterns = input
+        .iter()
+        .map(|s| NamePattern::new(s))
+        .collect::<Result<Vec<NamePattern>, anyhow::Error>>()?;
+    let mut missing_names = input
+        .iter()
+        .zip(&patterns)
+        .filter(|(_, p)| !pdb_names.iter().any(|n| p.matches(n)))
+        .map(|(s, _)| s)
+        .peekable();
 
     ensure!(
-        missing_atoms.peek().is_none(),
-        "No atom(s) found with identifier(s): {}",
-        missing_atoms.format(",")
+        missing_names.peek().is_none(),
+        "No {}(s) found with identifier(s): {}",
+        kind,
+        missing_names.format(",")
     );
 
-    let output_vec = pdb
+    Ok(patterns)
+}
+
+/// Takes a list of atom names (usually from command line input) and returns the IDs of all
+/// matching atoms. Glob patterns and regular expressions are supported.
+pub fn parse_atomic_list(input: &[&str], pdb: &PDB) -> Result<AtomList, anyhow::Error> {
+    let pdb_names: HashSet<&str> = pdb.atoms().map(Atom::name).collect();
+    let patterns = compile_name_patterns(input, &pdb_names, "atom")?;
+
+    Ok(pdb
         .par_atoms()
-        .filter(|x| {
-            input_vec
-                .iter()
-                .any(|y| x.name().to_lowercase() == y.to_lowercase())
-        })
+        .filter(|a| patterns.iter().any(|p| p.matches(a.name())))
         .map(Atom::serial_number)
-        .collect();
-
-    Ok(output_vec)
+        .collect())
 }
 
-/// Parses a string (usually taken from command line) and returns a list of residues given by a tuple
-/// of serial numbers and insertion codes. The input can be either a comma-separated list of serial numbers
-/// and insertion codes or residues names.
-pub fn parse_residue_list(input: &str, pdb: &PDB) -> Result<ResidueList, anyhow::Error> {
-    let residue_set: HashSet<String> = input.split(',').map(ToOwned::to_owned).collect();
+/// Takes a list of element symbols and returns the IDs of all atoms of these elements.
+pub fn parse_element_list(input: &[&str], pdb: &PDB) -> Result<AtomList, anyhow::Error> {
+    let input_set: HashSet<String> = input.iter().map(|e| e.to_lowercase()).collect();
 
     let pdb_set: HashSet<String> =
-        HashSet::from_par_iter(pdb.par_residues().map(|a| a.name().unwrap().to_lowercase()));
-    let mut missing_residues = residue_set.difference(&pdb_set).peekable();
+        HashSet::from_par_iter(pdb.par_atoms().map(|a| a.element().to_lowercase()));
+    let mut missing_elements = input_set.difference(&pdb_set).peekable();
 
     ensure!(
-        missing_residues.peek().is_none(),
-        "No residue(s) found with identifier(s): {}",
-        missing_residues.format(",")
+        missing_elements.peek().is_none(),
+        "No atom(s) found with element(s): {}",
+        missing_elements.format(",")
     );
 
     Ok(pdb
-        .par_residues()
-        .filter(|r| residue_set.contains(&r.name().unwrap_or("").to_lowercase()))
-        .map(Residue::serial_number)
+        .par_atoms()
+        .filter(|a| input_set.contains(&a.element().to_lowercase()))
+        .map(Atom::serial_number)
+        .collect())
+}
+
+/// Takes a list of residue names (usually from command line input) and returns a list of residues
+/// given by a tuple of chain IDs, serial numbers and insertion codes. Glob patterns and regular
+/// expressions are supported.
+pub fn parse_residue_list<'a>(
+    input: &[&str],
+    pdb: &'a PDB,
+) -> Result<ResidueList<'a>, anyhow::Error> {
+    let pdb_names: HashSet<&str> = pdb.residues().map(|r| r.name().unwrap_or("")).collect();
+    let patterns = compile_name_patterns(input, &pdb_names, "residue")?;
+
+    Ok(pdb
+        .chains()
+        .flat_map(|c| c.residues().map(move |r| (c, r)))
+        .filter(|(_, r)| patterns.iter().any(|p| p.matches(r.name().unwrap_or(""))))
+        .map(|(c, r)| (c.id(), r.serial_number(), r.insertion_code()))
         .collect())
 }
 
@@ -75,26 +155,52 @@ mod tests {
     #[test]
     fn parse_atomic_list_test() {
         // let num_list = "1,2:5,7,9-11";
-        let str_list = "OH,HH";
+        let str_list = ["OH", "HH"];
         let pdb = test_pdb("tests/test_blank.pdb");
 
         // assert_eq!(
         //     parse_atomic_list(num_list, &pdb).unwrap(),
         //     vec!(1, 2, 3, 4, 5, 7, 9, 10, 11)
         // );
-        assert_eq!(parse_atomic_list(str_list, &pdb).unwrap(), vec![39, 40]);
+        assert_eq!(parse_atomic_list(&str_list, &pdb).unwrap(), vec![39, 40]);
+        assert_eq!(parse_atomic_list(&["h*"], &pdb).unwrap().len(), 39);
+        assert_eq!(
+            parse_atomic_list(&["re:\"^C[AB]$\""], &pdb).unwrap(),
+            vec![4, 6, 21, 28, 30, 49, 51, 65, 67]
+        );
+        assert_eq!(parse_atomic_list(&["O?"], &pdb).unwrap(), vec![39]);
+        assert!(parse_atomic_list(&["OH", "X*"], &pdb).is_err());
+        assert!(parse_atomic_list(&["re:\"C[\""], &pdb).is_err());
+    }
+
+    #[test]
+    fn parse_element_list_test() {
+        let pdb = test_pdb("tests/test_blank.pdb");
+
+        assert_eq!(parse_element_list(&["o", "N"], &pdb).unwrap().len(), 17);
+        assert!(parse_element_list(&["cu"], &pdb).is_err());
     }
 
     #[test]
     fn parse_residue_list_test() {
         // let num_list = "1,2:5,6-7";
-        let str_list = "gly,wat";
+        let str_list = ["gly", "wat"];
         let pdb = test_pdb("tests/test_blank.pdb");
 
         // assert_eq!(
         //     parse_atomic_list(num_list, &pdb).unwrap(),
         //     vec!(1, 2, 3, 4, 5, 6, 7)
         // );
-        assert_eq!(parse_residue_list(str_list, &pdb).unwrap(), vec![2, 6, 7])
+        let residues: Vec<isize> = parse_residue_list(&str_list, &pdb)
+            .unwrap()
+            .into_iter()
+            .map(|(_, serial, _)| serial)
+            .collect();
+        assert_eq!(residues, vec![2, 6, 7]);
+        assert_eq!(parse_residue_list(&["HI?"], &pdb).unwrap().len(), 1);
+        assert_eq!(
+            parse_residue_list(&["re:\"^[GV]\""], &pdb).unwrap().len(),
+            3
+        );
     }
 }
@@ -1,3 +1,4 @@
+use super::get_residue_secondary_structure;
 use crate::residue_ascii::RESIDUE_ASCII;
 
 use std::borrow::ToOwned;
@@ -24,10 +25,12 @@ pub fn query_atoms<'a>(
         "Atom name",
         "Residue ID",
         "Residue Name",
+        "Secondary Structure",
         "QM",
         "Active",
     ]));
 
+    let structure = get_residue_secondary_structure(pdb, atom_list);
     let mut key: Option<&str> = None;
     let mut resname_vec = Vec::new();
 
@@ -41,6 +44,9 @@ pub fn query_atoms<'a>(
                 atom_hier.residue().serial_number().to_string()
                     + atom_hier.residue().insertion_code().unwrap_or(""),
                 atom_hier.residue().name().unwrap_or("").to_owned(),
+                structure
+                    .get(&atom_hier.atom().serial_number())
+                    .map_or("-".to_owned(), |s| s.to_string()),
                 atom_hier.atom().occupancy().to_string(),
                 atom_hier.atom().b_factor().to_string(),
             ]));
@@ -140,6 +146,7 @@ mod tests {
             "Atom name",
             "Residue ID",
             "Residue Name",
+            "Secondary Structure",
             "QM",
             "Active",
         ]));
@@ -149,6 +156,7 @@ mod tests {
             "N".to_owned(),
             1_u8.to_string(),
             "HIE".to_owned(),
+            "Coil".to_owned(),
             0.00.to_string(),
             0.00.to_string(),
         ]));
@@ -157,6 +165,7 @@ mod tests {
             "HA".to_owned(),
             1_u8.to_string(),
             "HIE".to_owned(),
+            "Coil".to_owned(),
             0.00.to_string(),
             0.00.to_string(),
         ]));
@@ -165,6 +174,7 @@ mod tests {
             "N".to_owned(),
             2_u8.to_string(),
             "GLY".to_owned(),
+            "Coil".to_owned(),
             0.00.to_string(),
             0.00.to_string(),
         ]));
@@ -0,0 +1,232 @@
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::PI;
+
+use super::in_region;
+use crate::options::Region;
+use crate::residue_names::is_water;
+
+use comfy_table::modifiers::{UTF8_ROUND_CORNERS, UTF8_SOLID_INNER_BORDERS};
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{Row, Table};
+use pdbtbx::{Atom, ContainsAtomConformer, ContainsAtomConformerResidue, PDB};
+
+/// Probe radius in A used if no other radius is given, corresponding to a water molecule.
+pub const PROBE_RADIUS: f64 = 1.4;
+
+/// Number of test points placed on the sphere around every atom.
+const SPHERE_POINTS: usize = 100;
+
+/// Van der Waals radii in A (Bondi, J. Phys. Chem. 1964). Elements without a tabulated radius,
+/// most notably many metals, are assigned 1.8 A.
+fn vdw_radius(element: &str) -> f64 {
+    match element.to_uppercase().as_str() {
+        "H" | "D" => 1.20,
+        "C" => 1.70,
+        "N" => 1.55,
+        "O" => 1.52,
+        "F" => 1.47,
+        "NA" => 2.27,
+        "MG" => 1.73,
+        "SI" => 2.10,
+        "P" => 1.80,
+        "S" => 1.80,
+        "CL" => 1.75,
+        "K" => 2.75,
+        "NI" => 1.63,
+        "CU" => 1.40,
+        "ZN" => 1.39,
+        "SE" => 1.90,
+        "BR" => 1.85,
+        "I" => 1.98,
+        _ => 1.80,
+    }
+}
+
+/// Returns evenly distributed points on the unit sphere (golden section spiral).
+fn sphere_points(n: usize) -> Vec<(f64, f64, f64)> {
+    let increment = PI * (3.0 - 5f64.sqrt());
+    (0..n)
+        .map(|i| {
+            let y = 1.0 - 2.0 * (i as f64 + 0.5) / n as f64;
+            let r = (1.0 - y * y).sqrt();
+            let phi = i as f64 * increment;
+            (r * phi.cos(), y, r * phi.sin())
+        })
+        .collect()
+}
+
+/// Calculates the solvent accessible surface area in A^2 of every atom with the algorithm of
+/// Shrake and Rupley (J. Mol. Biol. 1973). Water molecules are considered solvent, they neither
+/// occlude other atoms nor are they assigned a surface area.
+pub fn get_sasa(pdb: &PDB, probe_radius: f64) -> HashMap<usize, f64> {
+    let water: HashSet<usize> = pdb
+        .atoms_with_hierarchy()
+        .filter(|a| is_water(a.residue().name().unwrap_or("")))
+        .map(|a| a.atom().serial_number())
+        .collect();
+    let radius = |a: &Atom| vdw_radius(a.element()) + probe_radius;
+    let max_radius = pdb.atoms().map(radius).fold(0.0, f64::max);
+
+    let points = sphere_points(SPHERE_POINTS);
+    let tree = pdb.create_atom_rtree();
+
+    pdb.atoms()
+        .filter(|a| !water.contains(&a.serial_number()))
+        .map(|atom| {
+            let r = radius(atom);
+            let neighbors: Vec<(&Atom, f64)> = tree
+                .locate_within_distance(atom.pos(), (r + max_radius).powi(2))
+                .copied()
+                .filter(|n| n.serial_number() != atom.serial_number())
+                .filter(|n| !water.contains(&n.serial_number()))
+                .map(|n| (n, radius(n)))
+                .filter(|(n, n_radius)| atom.distance(n) < r + n_radius)
+                .collect();
+
+            let accessible = points
+                .iter()
+                .filter(|(x, y, z)| {
+                    let point = (atom.x() + r * x, atom.y() + r * y, atom.z() + r * z);
+                    neighbors.iter().all(|(n, n_radius)| {
+                        (point.0 - n.x()).powi(2)
+                            + (point.1 - n.y()).powi(2)
+                            + (point.2 - n.z()).powi(2)
+                            >= n_radius.powi(2)
+                    })
+                })
+                .count();
+
+            (
+                atom.serial_number(),
+                4.0 * PI * r.powi(2) * accessible as f64 / SPHERE_POINTS as f64,
+            )
+        })
+        .collect()
+}
+
+/// Lists the solvent accessible surface areas of the QM1, QM2 and active regions and of all
+/// residues. If atoms are given, only these are taken into account and listed individually
+/// as well. The whole structure is used for the calculation in any case.
+pub fn analyze_sasa(
+    pdb: &PDB,
+    atoms: Option<&[usize]>,
+    probe_radius: f64,
+) -> Result<(Table, Table, Option<Table>), anyhow::Error> {
+    ensure!(probe_radius >= 0.0, "Probe radius may not be negative!");
+    let sasa = get_sasa(pdb, probe_radius);
+    let atom_set: Option<HashSet<&usize>> = atoms.map(|l| l.iter().collect());
+    let included = |a: &Atom| {
+        sasa.contains_key(&a.serial_number())
+            && match &atom_set {
+                Some(set) => set.contains(&a.serial_number()),
+                None => true,
+            }
+    };
+    ensure!(
+        pdb.atoms().any(included),
+        "No atoms other than water selected!"
+    );
+
+    // Starts at positive zero, unlike the sum of an empty iterator
+    let total = |atoms: &[&Atom]| {
+        atoms
+            .iter()
+            .fold(0.0, |acc, a| acc + sasa[&a.serial_number()])
+    };
+    let new_table = || {
+        let mut table = Table::new();
+        table
+            .load_preset(UTF8_FULL)
+            .apply_modifier(UTF8_ROUND_CORNERS)
+            .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+        table
+    };
+
+    let mut region_table = new_table();
+    region_table.set_header(Row::from(vec!["Region", "Atoms", "SASA"]));
+    let regions = [Region::QM1, Region::QM2, Region::Active];
+    let in_table_region = |a: &Atom, region: Option<&Region>| match region {
+        Some(r) => in_region(a, *r),
+        None => true,
+    };
+    for region in regions.iter().map(Some).chain([None]) {
+        let region_atoms: Vec<&Atom> = pdb
+            .atoms()
+            .filter(|a| included(a) && in_table_region(a, region))
+            .collect();
+        region_table.add_row(Row::from(vec![
+            region.map_or("Total".to_owned(), |r| r.to_string()),
+            region_atoms.len().to_string(),
+            format!("{:.1}", total(&region_atoms)),
+        ]));
+    }
+
+    let mut residue_table = new_table();
+    residue_table.set_header(Row::from(vec!["Chain", "Residue", "Atoms", "SASA"]));
+    let mut atom_table = new_table();
+    atom_table.set_header(Row::from(vec!["Atom ID", "Atom", "Residue", "SASA"]));
+
+    for chain in pdb.chains() {
+        for residue in chain.residues() {
+            let residue_atoms: Vec<&Atom> = residue.atoms().filter(|a| included(a)).collect();
+            if residue_atoms.is_empty() {
+                continue;
+            }
+            let name = format!(
+                "{} {}{}",
+                residue.name().unwrap_or(""),
+                residue.serial_number(),
+                residue.insertion_code().unwrap_or("")
+            );
+
+            residue_table.add_row(Row::from(vec![
+                chain.id().to_owned(),
+                name.clone(),
+                residue_atoms.len().to_string(),
+                format!("{:.1}", total(&residue_atoms)),
+            ]));
+            for atom in residue_atoms {
+                atom_table.add_row(Row::from(vec![
+                    atom.serial_number().to_string(),
+                    atom.name().to_owned(),
+                    name.clone(),
+                    format!("{:.1}", sasa[&atom.serial_number()]),
+                ]));
+            }
+        }
+    }
+
+    Ok((region_table, residue_table, atoms.map(|_| atom_table)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdbtbx::StrictnessLevel;
+
+    fn test_pdb(path: &str) -> PDB {
+        let (pdb, _) = pdbtbx::open_pdb(path, StrictnessLevel::Strict).unwrap();
+        pdb
+    }
+
+    #[test]
+    fn sasa_test() {
+        let pdb = test_pdb("tests/test_sasa.pdb");
+
+        // The carbon atom is only covered by a water molecule which is considered solvent
+        let sasa = get_sasa(&pdb, PROBE_RADIUS);
+        assert_eq!(sasa.len(), 3);
+        assert!((sasa[&1] - 4.0 * PI * 3.1f64.powi(2)).abs() < 1e-6);
+        assert!((get_sasa(&pdb, 0.0)[&1] - 4.0 * PI * 1.7f64.powi(2)).abs() < 1e-6);
+
+        // Two overlapping oxygens lose the area of a spherical cap each
+        let exposed = 4.0 * PI * 2.92f64.powi(2) * (0.5 + 0.25 / 2.92);
+        assert!((sasa[&2] - exposed).abs() < 3.0);
+        assert!((sasa[&3] - exposed).abs() < 3.0);
+
+        assert!(analyze_sasa(&pdb, Some(&[4]), PROBE_RADIUS).is_err());
+        assert!(analyze_sasa(&pdb, None, -1.0).is_err());
+        let (_, _, atom_table) = analyze_sasa(&pdb, Some(&[1, 4]), PROBE_RADIUS).unwrap();
+        assert_eq!(atom_table.unwrap().row_iter().count(), 1);
+    }
+}
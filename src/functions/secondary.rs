@@ -0,0 +1,273 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::residue_names::is_protein;
+
+use pdbtbx::{Atom, PDB};
+use strum_macros::Display;
+
+/// Maximum distance in A between a peptide carbon and the following nitrogen.
+pub const PEPTIDE_BOND_DISTANCE: f64 = 2.5;
+/// Maximum nitrogen-oxygen distance in A for which hydrogen bond energies are calculated.
+const MAX_HBOND_DISTANCE: f64 = 5.2;
+/// Energy in kcal/mol below which backbone hydrogen bonds are assumed.
+const HBOND_ENERGY_CUTOFF: f64 = -0.5;
+
+/// Secondary structure of an amino acid residue. All helix types and beta bridges are merged
+/// into helices and strands, respectively.
+#[derive(Display, PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Ord, Hash)]
+pub enum SecondaryStructure {
+    Helix,
+    Strand,
+    Turn,
+    Coil,
+}
+
+/// Backbone atoms of a residue. The amide hydrogen is placed opposite to the carbonyl oxygen
+/// of the preceding residue if not present in the structure.
+struct Backbone<'a> {
+    n: &'a Atom,
+    c: &'a Atom,
+    o: &'a Atom,
+    h: Option<(f64, f64, f64)>,
+    atoms: Vec<usize>,
+    /// Index of the chain segment without breaks the residue belongs to
+    segment: usize,
+}
+
+/// Electrostatic energy in kcal/mol of the hydrogen bond between the carbonyl group of the
+/// first and the amide group of the second residue (Kabsch and Sander, Biopolymers 1983).
+fn hbond_energy(acceptor: &Backbone, donor: &Backbone) -> Option<f64> {
+    let h = donor.h?;
+    let distance = |a: &Atom, b: (f64, f64, f64)| {
+        ((a.x() - b.0).powi(2) + (a.y() - b.1).powi(2) + (a.z() - b.2).powi(2)).sqrt()
+    };
+    let (o, c, n) = (acceptor.o, acceptor.c, donor.n);
+    Some(
+        0.084
+            * 332.0
+            * (1.0 / o.distance(n) + 1.0 / distance(c, h)
+                - 1.0 / distance(o, h)
+                - 1.0 / c.distance(n)),
+    )
+}
+
+/// Collects the backbones of all protein residues in the order of the structure.
+fn get_backbones(pdb: &PDB) -> Vec<Backbone<'_>> {
+    let mut backbones: Vec<Backbone> = Vec::new();
+    let mut segment = 0;
+
+    for chain in pdb.chains() {
+        segment += 1;
+        for residue in chain.residues() {
+            let name = residue.name().unwrap_or("").to_uppercase();
+            let find = |names: &[&str]| residue.atoms().find(|a| names.contains(&a.name()));
+            let (n, c, o) = match (find(&["N"]), find(&["C"]), find(&["O"])) {
+                (Some(n), Some(c), Some(o)) if is_protein(&name) => (n, c, o),
+                _ => {
+                    segment += 1;
+                    continue;
+                }
+            };
+
+            let previous = backbones
+                .last()
+                .filter(|b| b.segment == segment && b.c.distance(n) <= PEPTIDE_BOND_DISTANCE);
+            if previous.is_none() {
+                segment += 1;
+            }
+            let h = match (find(&["H", "HN"]), previous) {
+                (Some(h), _) => Some(h.pos()),
+                (None, Some(p)) if !name.ends_with("PRO") => {
+                    let (dx, dy, dz) = (p.c.x() - p.o.x(), p.c.y() - p.o.y(), p.c.z() - p.o.z());
+                    let length = (dx * dx + dy * dy + dz * dz).sqrt();
+                    Some((
+                        n.x() + dx / length,
+                        n.y() + dy / length,
+                        n.z() + dz / length,
+                    ))
+                }
+                _ => None,
+            };
+
+            backbones.push(Backbone {
+                n,
+                c,
+                o,
+                h,
+                atoms: residue.atoms().map(|a| a.serial_number()).collect(),
+                segment,
+            });
+        }
+    }
+
+    backbones
+}
+
+/// Assigns secondary structure to all amino acid residues based on backbone hydrogen bonds
+/// in the manner of DSSP (Kabsch and Sander, Biopolymers 1983): residues covered by two
+/// consecutive n-turns (n = 3, 4, 5) form helices, residues in parallel or antiparallel
+/// bridges form strands and the inner residues of other turns are turns. Returns the
+/// secondary structure of every atom of these residues.
+pub fn get_secondary_structure(pdb: &PDB) -> HashMap<usize, SecondaryStructure> {
+    assign_secondary_structure(pdb, |_| true)
+}
+
+/// Assigns secondary structure like 'get_secondary_structure' but only to the amino acid
+/// residues containing any of the given atoms. Only the hydrogen bonds these residues depend
+/// on are calculated.
+pub fn get_residue_secondary_structure(
+    pdb: &PDB,
+    atom_list: &[usize],
+) -> HashMap<usize, SecondaryStructure> {
+    let atoms: HashSet<usize> = atom_list.iter().copied().collect();
+    assign_secondary_structure(pdb, |b| b.atoms.iter().any(|a| atoms.contains(a)))
+}
+
+fn assign_secondary_structure(
+    pdb: &PDB,
+    selected: impl Fn(&Backbone) -> bool,
+) -> HashMap<usize, SecondaryStructure> {
+    let backbones = get_backbones(pdb);
+    let count = backbones.len();
+    // Residue at the given offset from another one if both are connected without breaks
+    let offset = |i: usize, n: isize| -> Option<usize> {
+        let j = i as isize + n;
+        (j >= 0 && j < count as isize && backbones[i].segment == backbones[j as usize].segment)
+            .then_some(j as usize)
+    };
+
+    // The structure of a residue depends on hydrogen bonds accepted by up to six residues
+    // before and one after it as well as those donated by its direct neighbours
+    let targets: Vec<usize> = (0..count).filter(|&i| selected(&backbones[i])).collect();
+    let mut window = vec![false; count];
+    for &i in &targets {
+        for j in (-6..=1).filter_map(|n| offset(i, n)) {
+            window[j] = true;
+        }
+    }
+
+    let oxygens: HashMap<usize, usize> = backbones
+        .iter()
+        .enumerate()
+        .map(|(i, b)| (b.o.serial_number(), i))
+        .collect();
+    let nitrogens: HashMap<usize, usize> = backbones
+        .iter()
+        .enumerate()
+        .map(|(i, b)| (b.n.serial_number(), i))
+        .collect();
+
+    // Pairs of carbonyl (acceptor) and amide (donor) residue indices
+    let tree = pdb.create_atom_rtree();
+    let mut hbonds: HashSet<(usize, usize)> = HashSet::new();
+    let mut add_hbond = |i: usize, j: usize| {
+        if i != j
+            && matches!(hbond_energy(&backbones[i], &backbones[j]), Some(e) if e < HBOND_ENERGY_CUTOFF)
+        {
+            hbonds.insert((i, j));
+        }
+    };
+    for i in (0..count).filter(|&i| window[i]) {
+        for atom in tree.locate_within_distance(backbones[i].o.pos(), MAX_HBOND_DISTANCE.powi(2)) {
+            if let Some(&j) = nitrogens.get(&atom.serial_number()) {
+                add_hbond(i, j)
+            }
+        }
+        for atom in tree.locate_within_distance(backbones[i].n.pos(), MAX_HBOND_DISTANCE.powi(2)) {
+            if let Some(&j) = oxygens.get(&atom.serial_number()) {
+                add_hbond(j, i)
+            }
+        }
+    }
+
+    let hbond = |i: Option<usize>, j: Option<usize>| match (i, j) {
+        (Some(i), Some(j)) => hbonds.contains(&(i, j)),
+        _ => false,
+    };
+    let turn = |i: Option<usize>, n: isize| hbond(i, i.and_then(|i| offset(i, n)));
+    let bridge = |i: usize, j: usize| {
+        let (i_prev, i_next) = (offset(i, -1), offset(i, 1));
+        let (j_prev, j_next) = (offset(j, -1), offset(j, 1));
+        let (i, j) = (Some(i), Some(j));
+        let parallel =
+            (hbond(i_prev, j) && hbond(j, i_next)) || (hbond(j_prev, i) && hbond(i, j_next));
+        let antiparallel =
+            (hbond(i, j) && hbond(j, i)) || (hbond(i_prev, j_next) && hbond(j_prev, i_next));
+        parallel || antiparallel
+    };
+
+    let structure = |i: usize| {
+        let helix = [3, 4, 5].into_iter().any(|n| {
+            (0..n)
+                .filter_map(|k| offset(i, -k))
+                .any(|j| turn(offset(j, -1), n) && turn(Some(j), n))
+        });
+        let strand = || (0..count).any(|j| i.abs_diff(j) >= 3 && bridge(i, j));
+        let turned = || {
+            [3, 4, 5]
+                .into_iter()
+                .any(|n| (1..n).any(|k| turn(offset(i, -k), n)))
+        };
+
+        if helix {
+            SecondaryStructure::Helix
+        } else if strand() {
+            SecondaryStructure::Strand
+        } else if turned() {
+            SecondaryStructure::Turn
+        } else {
+            SecondaryStructure::Coil
+        }
+    };
+
+    targets
+        .into_iter()
+        .flat_map(|i| {
+            let s = structure(i);
+            backbones[i].atoms.iter().map(move |a| (*a, s))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdbtbx::StrictnessLevel;
+
+    fn test_pdb(path: &str) -> PDB {
+        let (pdb, _) = pdbtbx::open_pdb(path, StrictnessLevel::Strict).unwrap();
+        pdb
+    }
+
+    #[test]
+    fn secondary_structure_test() {
+        let pdb = test_pdb("tests/test_insert.pdb");
+        let structure = get_secondary_structure(&pdb);
+
+        // Alpha carbons of a helix (ALA 121 to 127), a strand (GLU 164 to THR 175), a turn and
+        // the coil at the N-terminus. The N-terminal HIM is no standard amino acid.
+        for id in [1738, 1826] {
+            assert_eq!(structure[&id], SecondaryStructure::Helix);
+        }
+        for id in [2357, 2525] {
+            assert_eq!(structure[&id], SecondaryStructure::Strand);
+        }
+        assert_eq!(structure[&159], SecondaryStructure::Turn);
+        assert_eq!(structure[&24], SecondaryStructure::Coil);
+        assert!(!structure.contains_key(&4));
+
+        // Restricting the assignment to some residues does not change their structure
+        let residues = get_residue_secondary_structure(&pdb, &[4, 24, 159, 1738, 2357]);
+        for id in [24, 159, 1738, 2357] {
+            assert_eq!(residues[&id], structure[&id]);
+        }
+        assert!(residues.iter().all(|(id, s)| structure[id] == *s));
+        assert!(residues.len() < structure.len());
+
+        // Without any backbone hydrogen bonds, the short peptide is a coil
+        let pdb = test_pdb("tests/test_blank.pdb");
+        let structure = get_secondary_structure(&pdb);
+        assert!(structure.values().all(|s| *s == SecondaryStructure::Coil));
+        assert!(!structure.contains_key(&78));
+    }
+}
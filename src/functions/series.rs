@@ -0,0 +1,144 @@
+use super::{
+    edit_atoms_unchecked, formal_charges, get_active_around_qm, get_atoms_within,
+    get_region_charge, in_region, remove_region, BondGraph, Layer, RegionCharge,
+};
+use crate::options::Region;
+
+use comfy_table::modifiers::{UTF8_ROUND_CORNERS, UTF8_SOLID_INNER_BORDERS};
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{Row, Table};
+use pdbtbx::PDB;
+
+/// One structure of a series of growing QM regions.
+#[derive(Debug, Clone)]
+pub struct SeriesStep {
+    pub radius: f64,
+    pub pdb: PDB,
+    pub qm1_atoms: usize,
+    pub qm1_residues: usize,
+    pub active_atoms: usize,
+    pub charge: RegionCharge,
+}
+
+/// Builds a series of structures whose QM1 regions consist of all whole residues within the
+/// given radii of the centre atoms. All structures share the same active region which is built
+/// from whole residues within 'active_radius' of the largest QM1 region. The radii are sorted
+/// ascendingly.
+pub fn get_series(
+    pdb: &PDB,
+    centre: &[usize],
+    radii: &[f64],
+    active_radius: f64,
+) -> Result<Vec<SeriesStep>, anyhow::Error> {
+    ensure!(!centre.is_empty(), "No atoms in the centre selection!");
+    ensure!(!radii.is_empty(), "No radii given!");
+    let mut radii = radii.to_vec();
+    radii.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    radii.dedup();
+
+    let mut base = pdb.clone();
+    remove_region(&mut base, None);
+    let qm1_lists: Vec<Vec<usize>> = radii
+        .iter()
+        .map(|r| get_atoms_within(&base, centre, *r, true, None))
+        .collect();
+
+    // The active region is built around the largest QM region so it contains all others
+    let mut largest = base.clone();
+    edit_atoms_unchecked(&mut largest, qm1_lists.last().unwrap(), "Add", Region::QM1);
+    let active = get_active_around_qm(&largest, active_radius, None)?;
+
+    let charges = formal_charges(pdb);
+    let graph = BondGraph::new(pdb);
+    let mut steps = Vec::with_capacity(radii.len());
+    for (radius, qm1) in radii.into_iter().zip(qm1_lists) {
+        let mut step_pdb = base.clone();
+        edit_atoms_unchecked(&mut step_pdb, &qm1, "Add", Region::QM1);
+        edit_atoms_unchecked(&mut step_pdb, &active, "Add", Region::Active);
+
+        steps.push(SeriesStep {
+            radius,
+            qm1_atoms: qm1.len(),
+            qm1_residues: step_pdb
+                .residues()
+                .filter(|r| r.atoms().any(|a| in_region(a, Region::QM1)))
+                .count(),
+            active_atoms: active.len(),
+            charge: get_region_charge(&step_pdb, &charges, &graph, &[Layer::QM1])?,
+            pdb: step_pdb,
+        });
+    }
+
+    Ok(steps)
+}
+
+/// Creates a summary table of the given series with the names of the written files.
+pub fn series_table(steps: &[SeriesStep], files: &[String]) -> Table {
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .apply_modifier(UTF8_SOLID_INNER_BORDERS);
+    table.set_header(Row::from(vec![
+        "Step",
+        "Radius",
+        "QM1 Atoms",
+        "QM1 Residues",
+        "Charge",
+        "Boundary Cuts",
+        "Active Atoms",
+        "File",
+    ]));
+
+    for (i, (step, file)) in steps.iter().zip(files).enumerate() {
+        table.add_row(Row::from(vec![
+            (i + 1).to_string(),
+            format!("{:.1}", step.radius),
+            step.qm1_atoms.to_string(),
+            step.qm1_residues.to_string(),
+            format!("{:+}", step.charge.charge),
+            step.charge.link_atoms.to_string(),
+            step.active_atoms.to_string(),
+            file.to_owned(),
+        ]));
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdbtbx::StrictnessLevel;
+
+    fn test_pdb(path: &str) -> PDB {
+        let (pdb, _) = pdbtbx::open_pdb(path, StrictnessLevel::Strict).unwrap();
+        pdb
+    }
+
+    #[test]
+    fn series_test() {
+        let pdb = test_pdb("tests/test_get_atomlist.pdb");
+        let steps = get_series(&pdb, &[9], &[5.0, 0.0, 5.0], 3.0).unwrap();
+        assert_eq!(steps.len(), 2);
+
+        // The smallest QM region is the centre residue, existing regions are discarded
+        assert_eq!(steps[0].radius, 0.0);
+        assert_eq!(steps[0].qm1_atoms, 18);
+        assert_eq!(steps[0].qm1_residues, 1);
+        assert!(steps[0].pdb.atoms().all(|a| a.occupancy() != 2.00));
+        assert!(steps[1].qm1_atoms > steps[0].qm1_atoms);
+
+        // The active region is shared and contains all QM atoms
+        assert_eq!(steps[0].active_atoms, steps[1].active_atoms);
+        for step in &steps {
+            assert!(step
+                .pdb
+                .atoms()
+                .all(|a| a.occupancy() != 1.00 || a.b_factor() == 1.00));
+        }
+
+        assert!(get_series(&pdb, &[], &[4.0], 3.0).is_err());
+        assert!(get_series(&pdb, &[9], &[], 3.0).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use pdbtbx::{Residue, PDB};
+
+use super::{chain_termini, BondGraph};
+use crate::prms::ForceField;
+use crate::rtf::Topology;
+
+/// Comparison of a residue of the structure with its topology template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResidueMatch {
+    pub chain: String,
+    pub residue: String,
+    pub name: String,
+    /// Name of the template including applied patches, None if no template was found.
+    pub template: Option<String>,
+    /// Template atoms not present in the residue.
+    pub missing: Vec<String>,
+    /// Residue atoms not present in the template.
+    pub extra: Vec<String>,
+    /// Atoms whose element differs from the element of their template atom type.
+    pub wrong_element: Vec<String>,
+    /// Bonds of the template between present atoms that are not found in the structure.
+    pub unbonded: Vec<(String, String)>,
+}
+
+impl ResidueMatch {
+    pub fn is_complete(&self) -> bool {
+        self.template.is_some()
+            && self.missing.is_empty()
+            && self.extra.is_empty()
+            && self.wrong_element.is_empty()
+            && self.unbonded.is_empty()
+    }
+}
+
+/// Template data mapped onto a structure.
+#[derive(Debug, Default)]
+pub struct TopologyAssignment {
+    /// Partial charges of all atoms matched to a template atom by atom ID.
+    pub charges: HashMap<usize, f64>,
+    pub residues: Vec<ResidueMatch>,
+}
+
+/// Maps the residue templates of a topology onto all residues of the structure. Terminal
+/// patches are applied to the charged termini given by `chain_termini`.
+pub fn assign_topology(pdb: &PDB, topology: &Topology) -> TopologyAssignment {
+    let graph = BondGraph::new(pdb);
+    let mut assignment = TopologyAssignment::default();
+
+    for chain in pdb.chains() {
+        let residues: Vec<&Residue> = chain.residues().collect();
+        for (residue, (nterm, cterm)) in residues.iter().zip(chain_termini(&residues)) {
+            let atom_names: Vec<&str> = residue.atoms().map(|a| a.name()).collect();
+            let template = topology.residue_template(residue.name().unwrap_or(""), nterm, cterm);
+
+            let mut residue_match = ResidueMatch {
+                chain: chain.id().to_owned(),
+                residue: residue.serial_number().to_string()
+                    + residue.insertion_code().unwrap_or(""),
+                name: residue.name().unwrap_or("").to_owned(),
+                template: template.as_ref().map(|t| t.name.clone()),
+                missing: Vec::new(),
+                extra: Vec::new(),
+                wrong_element: Vec::new(),
+                unbonded: Vec::new(),
+            };
+
+            let template = match template {
+                Some(t) => t,
+                None => {
+                    residue_match.extra = atom_names.iter().map(|n| n.to_string()).collect();
+                    assignment.residues.push(residue_match);
+                    continue;
+                }
+            };
+
+            let is_hydrogen = |name: &str| {
+                template
+                    .atom(name)
+                    .and_then(|a| topology.element(&a.atom_type))
+                    == Some("H")
+            };
+
+            // Atom IDs by template atom name
+            let mut matched: HashMap<&str, usize> = HashMap::new();
+            for atom in residue.atoms() {
+                match template.match_atom(atom.name(), &atom_names) {
+                    Some(t) if !matched.contains_key(t.name.as_str()) => {
+                        matched.insert(&t.name, atom.serial_number());
+                        assignment.charges.insert(atom.serial_number(), t.charge);
+
+                        if let Some(element) = topology.element(&t.atom_type) {
+                            if !atom.element().is_empty()
+                                && !atom.element().eq_ignore_ascii_case(element)
+                            {
+                                residue_match.wrong_element.push(atom.name().to_owned())
+                            }
+                        }
+                    }
+                    _ => residue_match.extra.push(atom.name().to_owned()),
+                }
+            }
+
+            residue_match.missing = template
+                .atoms
+                .iter()
+                .filter(|a| !matched.contains_key(a.name.as_str()))
+                .map(|a| a.name.clone())
+                .collect();
+            residue_match.unbonded = template
+                .bonds
+                .iter()
+                .filter_map(|(a, b)| {
+                    Some(((a, matched.get(a.as_str())?), (b, matched.get(b.as_str())?)))
+                })
+                .filter(|((_, i), (_, j))| !graph.neighbors(**i).contains(j))
+                // Bonds between hydrogens only constrain water geometry
+                .filter(|((a, _), (b, _))| !(is_hydrogen(a) && is_hydrogen(b)))
+                .map(|((a, _), (b, _))| (a.clone(), b.clone()))
+                .collect();
+
+            assignment.residues.push(residue_match);
+        }
+    }
+
+    assignment
+}
+
+/// Returns the IDs of all atoms in the order of the force field, which has to match the
+/// order of the PDB file.
+fn forcefield_atom_ids(pdb: &PDB, forcefield: &ForceField) -> Result<Vec<usize>, anyhow::Error> {
+    ensure!(
+        pdb.atom_count() == forcefield.atoms.len(),
+        "Number of atoms in PDB file ({}) and force field ({}) differ!",
+        pdb.atom_count(),
+        forcefield.atoms.len()
+    );
+    Ok(pdb.atoms().map(|a| a.serial_number()).collect())
+}
+
+/// Returns the partial charges of the force field by atom ID.
+pub fn forcefield_charges(
+    pdb: &PDB,
+    forcefield: &ForceField,
+) -> Result<HashMap<usize, f64>, anyhow::Error> {
+    let ids = forcefield_atom_ids(pdb, forcefield)?;
+    Ok(ids
+        .into_iter()
+        .zip(forcefield.atoms.iter().map(|a| a.charge))
+        .collect())
+}
+
+/// Returns the connectivity given by the bonds of the force field.
+pub fn forcefield_bond_graph(
+    pdb: &PDB,
+    forcefield: &ForceField,
+) -> Result<BondGraph, anyhow::Error> {
+    let ids = forcefield_atom_ids(pdb, forcefield)?;
+    Ok(BondGraph::from_bonds(
+        forcefield.bonds.iter().map(|&(a, b)| (ids[a], ids[b])),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdbtbx::StrictnessLevel;
+
+    fn test_pdb(path: &str) -> PDB {
+        let (pdb, _) = pdbtbx::open_pdb(path, StrictnessLevel::Strict).unwrap();
+        pdb
+    }
+
+    #[test]
+    fn assign_topology_test() {
+        let pdb = test_pdb("tests/test_blank.pdb");
+        let assignment = assign_topology(&pdb, &Topology::charmm36_protein());
+
+        // The first amino acid is a charged N-terminus lacking its third hydrogen, the last one
+        // a charged C-terminus lacking its second oxygen
+        let hie = &assignment.residues[0];
+        assert_eq!(hie.template.as_deref(), Some("HSE+NTER"));
+        assert_eq!(hie.missing, vec!["HT3"]);
+        assert!(hie.extra.is_empty());
+        let glu = &assignment.residues[4];
+        assert_eq!(glu.template.as_deref(), Some("GLU+CTER"));
+        assert_eq!(glu.missing, vec!["OT2"]);
+        assert!(assignment.residues[1..4].iter().all(|r| r.is_complete()));
+        assert!(assignment.residues[5..].iter().all(|r| r.is_complete()));
+
+        // All atoms have charges, the C-terminal GLU has a charge of -2 without the missing OT2
+        assert_eq!(assignment.charges.len(), pdb.atom_count());
+        let glu_charge: f64 = pdb
+            .residues()
+            .filter(|r| r.name() == Some("GLU"))
+            .flat_map(|r| r.atoms())
+            .map(|a| assignment.charges[&a.serial_number()])
+            .sum();
+        assert!((glu_charge + 2.0 - 0.67).abs() < 1e-6);
+    }
+
+    #[test]
+    fn forcefield_test() {
+        let pdb = test_pdb("tests/test_bonds.pdb");
+        let forcefield = ForceField::from_file("tests/test_bonds.prms").unwrap();
+
+        let charges = forcefield_charges(&pdb, &forcefield).unwrap();
+        assert_eq!(charges[&1], -0.834);
+        assert_eq!(charges[&7], 2.0);
+        let graph = forcefield_bond_graph(&pdb, &forcefield).unwrap();
+        assert_eq!(graph.neighbors(1), &[2, 3, 7]);
+        assert_eq!(graph.neighbors(5), &[4]);
+
+        let pdb = test_pdb("tests/test_blank.pdb");
+        assert!(forcefield_charges(&pdb, &forcefield).is_err());
+    }
+}
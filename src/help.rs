@@ -23,6 +23,9 @@ SUBCOMMANDS:
     Analyse              Analyze PDB file and QM1/QM2/Active region
     Write                Write PDB structure information to file or stdout
     Measure              Measure distances, angles and dihedrals between atoms
+    Define               Define named selections for later reuse
+    Check                Check QM1/QM2/Active region for consistency
+    Series               Write structures with growing QM1 regions
 
 Calling a subcommand with the '--help/-h' flag will display a help message for it";
 
@@ -45,6 +48,9 @@ SUBCOMMANDS:
     Analyse              Analyze PDB file and QM1/QM2/Active region
     Write                Write PDB structure information to file or stdout
     Measure              Measure distances, angles and dihedrals between atoms
+    Define               Define named selections for later reuse
+    Check                Check QM1/QM2/Active region for consistency
+    Series               Write structures with growing QM1 regions
 
 Calling a subcommand with the '--help/-h' flag will display a help message for it";
 
@@ -59,5 +65,8 @@ SUBCOMMANDS:
     Analyse              Analyze PDB file and QM1/QM2/Active region
     Write                Write PDB structure information to file or stdout
     Measure              Measure distances, angles and dihedrals between atoms
+    Define               Define named selections for later reuse
+    Check                Check QM1/QM2/Active region for consistency
+    Series               Write structures with growing QM1 regions
 
 Calling a subcommand with the '--help/-h' flag will display a help message for it";
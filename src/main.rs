@@ -13,11 +13,15 @@ extern crate anyhow;
 extern crate pest_derive;
 
 mod dispatch;
+mod elements;
 mod functions;
 mod help;
 mod options;
+mod prms;
 mod residue_ascii;
+mod residue_names;
 mod revertable;
+mod rtf;
 mod selection;
 mod shell;
 
@@ -44,6 +48,7 @@ use dispatch::dispatch;
 use help::{HELP_INTER, HELP_LONG, HELP_SHORT};
 use options::{clap_args, Mode};
 use revertable::Revertable;
+use selection::Definitions;
 use shell::ShellHelper;
 
 struct PDBCacher<T>
@@ -192,6 +197,7 @@ fn run() -> Result<(), anyhow::Error> {
         rl.bind_sequence(KeyEvent::alt('p'), Cmd::HistorySearchBackward);
 
         let mut pdb = read_pdb()?;
+        let mut definitions = Definitions::new();
 
         let mut edit_ops: Vec<Revertable> = Vec::new();
         let mut edit_ops_index = 0;
@@ -273,7 +279,7 @@ fn run() -> Result<(), anyhow::Error> {
                 }
             };
 
-            match dispatch(&mode, &mut pdb, filename) {
+            match dispatch(&mode, &mut pdb, filename, &mut definitions) {
                 Ok(opt_op) => {
                     if let Some(edit_op) = opt_op {
                         // ensures the undo/redo tree remains via cutting stale branches
@@ -333,6 +339,7 @@ fn run() -> Result<(), anyhow::Error> {
         // More convenient so the args can be reused without cloning
         let args_vec: Vec<&str> = args.map(str::trim).collect();
         let mut pdb_cache = PDBCacher::new(read_pdb);
+        let mut definitions = Definitions::new();
 
         // Test for input errors before actually processing anything
         for (i, arg) in args_vec.iter().enumerate() {
@@ -368,7 +375,7 @@ fn run() -> Result<(), anyhow::Error> {
                 Err(e) => bail!(e.to_string()),
             };
 
-            if let Err(e) = dispatch(&mode, pdb, filename) {
+            if let Err(e) = dispatch(&mode, pdb, filename, &mut definitions) {
                 bail!(
                     "\n{}: '{}'\n\n{}",
                     "ERROR DURING PROCESSING OF INPUT".red(),
@@ -64,6 +64,102 @@ pub fn clap_args() -> Command<'static> {
                     .long("contacts")
                     .short('n')
             )
+            .arg(
+                Arg::new("Boundary")
+                    .help("Find bonds crossing QM/MM boundaries")
+                    .long("boundary")
+                    .short('b')
+            )
+            .arg(
+                Arg::new("Charge")
+                    .help("Estimate charge and number of electrons of QM regions")
+                    .long("charge")
+                    .short('e')
+            )
+            .arg(
+                Arg::new("Multiplicity")
+                    .help("Multiplicity to check the number of electrons against")
+                    .long("mult")
+                    .short('m')
+                    .takes_value(true)
+                    .requires("Charge")
+            )
+            .arg(
+                Arg::new("Topology")
+                    .help("Compare residues with topology templates")
+                    .long("topology")
+                    .short('p')
+            )
+            .arg(
+                Arg::new("Completeness")
+                    .help("Report missing atoms of amino acids, chain breaks and missing residues")
+                    .long("completeness")
+                    .short('k')
+            )
+            .arg(
+                Arg::new("Rtf")
+                    .help("CHARMM topology file used instead of the CHARMM36 protein topology")
+                    .long("rtf")
+                    .short('f')
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::new("Prms")
+                    .help("ORCA force field file (ORCAFF.prms) to validate and take charges and bonds from")
+                    .long("prms")
+                    .short('s')
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::new("MetalSite")
+                    .help("Analyze the coordination of the metal atoms in the given selection")
+                    .long("metalsite")
+                    .short('g')
+                    .takes_value(true)
+                    .multiple_values(true)
+            )
+            .arg(
+                Arg::new("HBonds")
+                    .help("Find hydrogen bonds, optionally only those involving the given selection")
+                    .long("hbonds")
+                    .short('d')
+                    .takes_value(true)
+                    .multiple_values(true)
+                    .min_values(0)
+            )
+            .arg(
+                Arg::new("Environment")
+                    .help("List charged groups and salt bridges around the given selection")
+                    .long("environment")
+                    .short('v')
+                    .takes_value(true)
+                    .multiple_values(true)
+            )
+            .arg(
+                Arg::new("Radius")
+                    .help("Radius of the environment in Angstrom (default: 8)")
+                    .long("radius")
+                    .short('u')
+                    .takes_value(true)
+                    .requires("Environment")
+            )
+            .arg(
+                Arg::new("Sasa")
+                    .help("Calculate solvent accessible surface areas, optionally only of the given selection")
+                    .long("sasa")
+                    .short('x')
+                    .takes_value(true)
+                    .multiple_values(true)
+                    .min_values(0)
+            )
+            .arg(
+                Arg::new("Probe")
+                    .help("Probe radius for the surface area in Angstrom (default: 1.4)")
+                    .long("probe")
+                    .short('w')
+                    .takes_value(true)
+                    .requires("Sasa")
+            )
             .group(ArgGroup::new("target").args(&["Residues", "Atoms"]).requires("region"))
             .group(ArgGroup::new("region").args(&["QM1", "QM2", "Active"]).requires("target"))
             .group(ArgGroup::new("distances").args(&["Clashes", "Contacts"]))
@@ -73,7 +169,7 @@ pub fn clap_args() -> Command<'static> {
             .visible_aliases(&["add", "A", "a"])
             .arg(Arg::new("Input")
                 .help("Input for atom selection")
-                .required(true)
+                .required_unless_present("AroundQM")
                 .multiple_values(true))
             .arg(
                 Arg::new("QM1")
@@ -105,6 +201,21 @@ pub fn clap_args() -> Command<'static> {
                     .long("backbone")
                     .short('b')
             )
+            .arg(
+                Arg::new("AroundQM")
+                    .help("Replace active region with whole residues within the given radius of QM atoms")
+                    .long("around-qm")
+                    .takes_value(true)
+                    .requires("Active")
+                    .conflicts_with_all(&["Input", "partial"])
+            )
+            .arg(
+                Arg::new("MaxAtoms")
+                    .help("Maximum number of atoms in the active region built around the QM atoms")
+                    .long("max-atoms")
+                    .takes_value(true)
+                    .requires("AroundQM")
+            )
             .group(
                 ArgGroup::new("partial")
                     .args(&["Sidechain", "Backbone"])
@@ -185,11 +296,85 @@ pub fn clap_args() -> Command<'static> {
                     .short('w')
                     .conflicts_with_all(&["QM1", "QM2", "Active", "Atoms", "Residues"])
             )
+            .arg(
+                Arg::new("Orca")
+                    .help("Write ORCA QM/MM input")
+                    .long("orca")
+                    .short('o')
+                    .conflicts_with_all(&["State", "Overwrite"])
+            )
+            .arg(
+                Arg::new("Xyz")
+                    .help("Write QM1 atoms as XYZ cluster model")
+                    .long("xyz")
+                    .short('x')
+                    .conflicts_with_all(&["State", "Overwrite", "Orca"])
+            )
+            .arg(
+                Arg::new("Cap")
+                    .help("Saturate cut bonds of the cluster model with hydrogens")
+                    .long("cap")
+                    .short('c')
+                    .requires("Xyz")
+            )
+            .arg(
+                Arg::new("WithQM2")
+                    .help("Include QM2 atoms in the cluster model")
+                    .long("qm2")
+                    .requires("Xyz")
+            )
+            .arg(
+                Arg::new("Check")
+                    .help("Check QM and active regions before writing")
+                    .long("check")
+                    .short('k')
+            )
             .group(
                 ArgGroup::new("output")
                     .args(&["Outfile", "Overwrite"])
             )
         )
+        .subcommand(Command::new("Series")
+            .about("Write structures with growing QM regions")
+            .visible_aliases(&["series"])
+            .arg(Arg::new("Input")
+                .help("Input for selection of the centre atoms")
+                .required(true)
+                .multiple_values(true))
+            .arg(
+                Arg::new("Radii")
+                    .help("Comma-separated radii of the QM regions")
+                    .long("radii")
+                    .short('r')
+                    .takes_value(true)
+                    .required(true)
+            )
+            .arg(
+                Arg::new("ActiveRadius")
+                    .help("Radius of the active region around the largest QM region")
+                    .long("active")
+                    .short('a')
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::new("Prefix")
+                    .help("Prefix of the written files")
+                    .long("prefix")
+                    .short('p')
+                    .takes_value(true)
+            )
+        )
+        .subcommand(Command::new("Check")
+            .about("Check QM and active regions for consistency")
+            .visible_aliases(&["check"])
+            .arg(
+                Arg::new("MaxActive")
+                    .help("Maximum number of active atoms")
+                    .long("max-active")
+                    .short('m')
+                    .takes_value(true)
+            )
+        )
         .subcommand(Command::new("Measure")
             .about("Measure distances and angles")
             .visible_aliases(&["measure"])
@@ -216,4 +401,11 @@ pub fn clap_args() -> Command<'static> {
                     .required(true)
                 )
             )
+        .subcommand(Command::new("Define")
+            .about("Define named selections")
+            .visible_aliases(&["define"])
+            .arg(Arg::new("Input")
+                .help("Name and selection in the form '<name> = <selection>'")
+                .multiple_values(true))
+        )
 }
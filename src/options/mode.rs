@@ -1,5 +1,7 @@
 use std::str::FromStr;
 
+use crate::functions::PROBE_RADIUS;
+
 use anyhow::{Context, Result};
 use itertools::Itertools;
 use strum::VariantNames;
@@ -14,11 +16,22 @@ pub enum Mode<'a> {
         region: Option<Region>,
         target: Option<Target>,
         distance: Option<Distance>,
+        boundary: bool,
+        multiplicity: Option<usize>,
+        topology: bool,
+        completeness: bool,
+        rtf: Option<String>,
+        prms: Option<String>,
+        metalsite: Option<String>,
+        hbonds: Option<Option<String>>,
+        environment: Option<(String, f64)>,
+        sasa: Option<(Option<String>, f64)>,
     },
     Add {
         region: Option<Region>,
         partial: Option<Partial>,
         selection: Option<String>,
+        around_qm: Option<QMShell>,
     },
     Remove {
         region: Option<Region>,
@@ -28,10 +41,25 @@ pub enum Mode<'a> {
     Write {
         output: Option<Output<'a>>,
         state: bool,
+        orca: bool,
+        xyz: Option<Cluster>,
+        check: bool,
+    },
+    Check {
+        max_active: Option<usize>,
+    },
+    Series {
+        centre: String,
+        radii: Vec<f64>,
+        active_radius: f64,
+        prefix: Option<String>,
     },
     Measure {
         measure_target: MeasureTarget,
     },
+    Define {
+        definition: Option<(String, String)>,
+    },
 }
 
 #[derive(Display, PartialEq, Debug, Clone, Copy, PartialOrd, EnumString, EnumVariantNames)]
@@ -53,6 +81,20 @@ pub enum MeasureTarget {
     Atoms(Vec<usize>),
 }
 
+/// Options for building the active region from whole residues around the QM atoms.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct QMShell {
+    pub radius: f64,
+    pub max_atoms: Option<usize>,
+}
+
+/// Options for writing the QM region as cluster model.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Cluster {
+    pub include_qm2: bool,
+    pub cap: bool,
+}
+
 #[derive(Display, PartialEq, Debug, Clone, EnumString, EnumVariantNames)]
 pub enum Output<'a> {
     Outfile(&'a str),
@@ -103,10 +145,64 @@ impl<'a> Mode<'a> {
                     .find(|x| matches.subcommand_matches("Analyze").unwrap().is_present(x))
                     .map(|s| Distance::from_str(s).unwrap());
 
+                let analyze_matches = matches.subcommand_matches("Analyze").unwrap();
+                // The multiplicity defaults to a singlet if only the charge flag is given
+                let multiplicity = match analyze_matches.value_of("Multiplicity") {
+                    Some(m) => Some(
+                        m.parse::<usize>()
+                            .ok()
+                            .filter(|m| *m > 0)
+                            .context(format!("Invalid input for multiplicity: {}", m))?,
+                    ),
+                    None if analyze_matches.is_present("Charge") => Some(1),
+                    None => None,
+                };
+                let radius = match analyze_matches.value_of("Radius") {
+                    Some(r) => r
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|r| *r > 0.0)
+                        .context(format!("Invalid input for radius: {}", r))?,
+                    None => 8.0,
+                };
+                let probe_radius = match analyze_matches.value_of("Probe") {
+                    Some(r) => r
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|r| *r >= 0.0)
+                        .context(format!("Invalid input for probe radius: {}", r))?,
+                    None => PROBE_RADIUS,
+                };
+
                 Ok(Mode::Analyze {
                     region,
                     target,
                     distance,
+                    boundary: analyze_matches.is_present("Boundary"),
+                    multiplicity,
+                    topology: analyze_matches.is_present("Topology"),
+                    completeness: analyze_matches.is_present("Completeness"),
+                    rtf: analyze_matches.value_of("Rtf").map(|s| s.to_owned()),
+                    prms: analyze_matches.value_of("Prms").map(|s| s.to_owned()),
+                    metalsite: analyze_matches
+                        .values_of("MetalSite")
+                        .map(|mut i| i.join(" ")),
+                    hbonds: analyze_matches.is_present("HBonds").then(|| {
+                        analyze_matches
+                            .values_of("HBonds")
+                            .map(|mut i| i.join(" "))
+                            .filter(|s| !s.is_empty())
+                    }),
+                    environment: analyze_matches
+                        .values_of("Environment")
+                        .map(|mut i| (i.join(" "), radius)),
+                    sasa: analyze_matches.is_present("Sasa").then(|| {
+                        let selection = analyze_matches
+                            .values_of("Sasa")
+                            .map(|mut i| i.join(" "))
+                            .filter(|s| !s.is_empty());
+                        (selection, probe_radius)
+                    }),
                 })
             }
             Some("Add") => {
@@ -120,14 +216,31 @@ impl<'a> Mode<'a> {
                     .find(|x| matches.subcommand_matches("Add").unwrap().is_present(x))
                     .map(|s| Partial::from_str(s).unwrap());
 
+                let add_matches = matches.subcommand_matches("Add").unwrap();
+                let around_qm = match add_matches.value_of("AroundQM") {
+                    Some(r) => {
+                        let radius = r
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|r| *r >= 0.0)
+                            .context(format!("Invalid input for radius: {}", r))?;
+                        let max_atoms = match add_matches.value_of("MaxAtoms") {
+                            Some(m) => Some(
+                                m.parse::<usize>()
+                                    .context(format!("Invalid input for atom count: {}", m))?,
+                            ),
+                            None => None,
+                        };
+                        Some(QMShell { radius, max_atoms })
+                    }
+                    None => None,
+                };
+
                 Ok(Mode::Add {
                     region,
-                    selection: matches
-                        .subcommand_matches("Add")
-                        .unwrap()
-                        .values_of("Input")
-                        .map(|mut i| i.join(" ")),
+                    selection: add_matches.values_of("Input").map(|mut i| i.join(" ")),
                     partial,
+                    around_qm,
                 })
             }
             Some("Remove") => {
@@ -175,8 +288,66 @@ impl<'a> Mode<'a> {
                         .subcommand_matches("Write")
                         .unwrap()
                         .is_present("State"),
+                    orca: matches
+                        .subcommand_matches("Write")
+                        .unwrap()
+                        .is_present("Orca"),
+                    xyz: matches
+                        .subcommand_matches("Write")
+                        .filter(|m| m.is_present("Xyz"))
+                        .map(|m| Cluster {
+                            include_qm2: m.is_present("WithQM2"),
+                            cap: m.is_present("Cap"),
+                        }),
+                    check: matches
+                        .subcommand_matches("Write")
+                        .unwrap()
+                        .is_present("Check"),
                 })
             }
+            Some("Series") => {
+                let series_matches = matches.subcommand_matches("Series").unwrap();
+                let mut radii = Vec::new();
+                for r in series_matches.value_of("Radii").unwrap().split(',') {
+                    radii.push(
+                        r.trim()
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|r| *r >= 0.0)
+                            .context(format!("Invalid input for radius: {}", r))?,
+                    );
+                }
+                // The active region extends 8 A beyond the largest QM region by default
+                let active_radius = match series_matches.value_of("ActiveRadius") {
+                    Some(r) => r
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|r| *r >= 0.0)
+                        .context(format!("Invalid input for radius: {}", r))?,
+                    None => 8.0,
+                };
+
+                Ok(Mode::Series {
+                    centre: series_matches.values_of("Input").unwrap().join(" "),
+                    radii,
+                    active_radius,
+                    prefix: series_matches.value_of("Prefix").map(|s| s.to_owned()),
+                })
+            }
+            Some("Check") => {
+                let max_active = match matches
+                    .subcommand_matches("Check")
+                    .unwrap()
+                    .value_of("MaxActive")
+                {
+                    Some(m) => Some(
+                        m.parse::<usize>()
+                            .context(format!("Invalid input for atom count: {}", m))?,
+                    ),
+                    None => None,
+                };
+                Ok(Mode::Check { max_active })
+            }
             Some("Measure") => {
                 let measure_str = MeasureTarget::VARIANTS
                     .iter()
@@ -234,6 +405,34 @@ impl<'a> Mode<'a> {
 
                 measure
             }
+            Some("Define") => {
+                let definition = match matches
+                    .subcommand_matches("Define")
+                    .unwrap()
+                    .values_of("Input")
+                {
+                    Some(mut i) => {
+                        let input = i.join(" ");
+                        let (name, selection) = input.split_once('=').ok_or_else(|| {
+                            anyhow!("Selections must be defined as '<name> = <selection>'")
+                        })?;
+                        let (name, selection) = (name.trim(), selection.trim());
+
+                        ensure!(
+                            !name.is_empty()
+                                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                            "Invalid name for selection: '{}'. Only letters, digits and underscores are allowed",
+                            name
+                        );
+                        ensure!(!selection.is_empty(), "No selection given for '{}'", name);
+
+                        Some((name.to_owned(), selection.to_owned()))
+                    }
+                    None => None,
+                };
+
+                Ok(Mode::Define { definition })
+            }
             _ => unreachable!(),
         }
     }
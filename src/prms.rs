@@ -0,0 +1,181 @@
+// Parser for ORCA force field parameter files (ORCAFF.prms). Only the simplified layout described
+// in the README is supported, it has not been verified against a specific orca_mm version.
+
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+
+use crate::elements::atomic_number;
+
+/// Atom entry of the $atoms section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrmsAtom {
+    pub atomic_number: usize,
+    pub charge: f64,
+    /// Lennard-Jones well depth in kcal/mol.
+    pub epsilon: f64,
+    /// Half the Lennard-Jones minimum distance in A.
+    pub rmin_half: f64,
+}
+
+/// Atoms and bonds of a force field parameter file. Atoms are given in the order of the
+/// PDB file, bonds by the 0-based positions of their atoms.
+#[derive(Debug, Default)]
+pub struct ForceField {
+    pub atoms: Vec<PrmsAtom>,
+    pub bonds: Vec<(usize, usize)>,
+}
+
+impl ForceField {
+    pub fn from_file(path: &str) -> Result<ForceField, anyhow::Error> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read force field file: {}", path))?;
+        content
+            .parse()
+            .with_context(|| format!("Failed to parse force field file: {}", path))
+    }
+}
+
+/// Parses a number of the given entry line, naming the line and quantity on failure.
+fn parse_field<T: FromStr>(field: Option<&&str>, name: &str, line: usize) -> Result<T> {
+    field
+        .and_then(|f| f.parse().ok())
+        .with_context(|| format!("Invalid or missing {} in line {}", name, line))
+}
+
+impl FromStr for ForceField {
+    type Err = anyhow::Error;
+
+    /// Reads the $atoms and $bonds sections. Every section starts with the number of entries,
+    /// atoms are numbered starting at 1 and bonds refer to these numbers. Atoms list exactly
+    /// their element (symbol or atomic number), partial charge and Lennard-Jones parameters,
+    /// atom lines with other columns are rejected since their element cannot be told apart
+    /// from e.g. an atom type.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut forcefield = ForceField::default();
+        let mut section = String::new();
+        // Number of entries given at the start of the atom and bond sections
+        let mut counts: HashMap<String, usize> = HashMap::new();
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('$') {
+                section = name.trim().to_lowercase();
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if (section == "atoms" || section == "bonds") && !counts.contains_key(&section) {
+                let count = parse_field(fields.first(), "number of entries", i + 1)?;
+                counts.insert(section.clone(), count);
+                continue;
+            }
+
+            match section.as_str() {
+                "atoms" => {
+                    ensure!(
+                        fields.len() == 5,
+                        "Unsupported atom entry in line {}: Expected 5 columns (number, element, \
+                         charge, LJ epsilon, LJ Rmin/2) but found {}",
+                        i + 1,
+                        fields.len()
+                    );
+                    let index: usize = parse_field(fields.first(), "atom number", i + 1)?;
+                    ensure!(
+                        index == forcefield.atoms.len() + 1,
+                        "Atom number {} out of order in line {}",
+                        index,
+                        i + 1
+                    );
+                    let element = fields
+                        .get(1)
+                        .with_context(|| format!("Missing element in line {}", i + 1))?;
+                    let atomic_number = element
+                        .parse()
+                        .ok()
+                        .or_else(|| atomic_number(element))
+                        .with_context(|| {
+                            format!("Unknown element '{}' in line {}", element, i + 1)
+                        })?;
+
+                    forcefield.atoms.push(PrmsAtom {
+                        atomic_number,
+                        charge: parse_field(fields.get(2), "charge", i + 1)?,
+                        epsilon: parse_field(fields.get(3), "LJ epsilon", i + 1)?,
+                        rmin_half: parse_field(fields.get(4), "LJ Rmin/2", i + 1)?,
+                    });
+                }
+                "bonds" => {
+                    let a: usize = parse_field(fields.first(), "atom number", i + 1)?;
+                    let b: usize = parse_field(fields.get(1), "atom number", i + 1)?;
+                    ensure!(a > 0 && b > 0, "Atom numbers start at 1 in line {}", i + 1);
+                    forcefield.bonds.push((a - 1, b - 1));
+                }
+                _ => {}
+            }
+        }
+
+        ensure!(
+            !forcefield.atoms.is_empty(),
+            "No atoms found in force field file"
+        );
+        if let Some(&(a, b)) = forcefield
+            .bonds
+            .iter()
+            .find(|(a, b)| a.max(b) >= &forcefield.atoms.len())
+        {
+            bail!("Bond {}-{} refers to nonexistent atom", a + 1, b + 1)
+        }
+        for (name, found) in [
+            ("atoms", forcefield.atoms.len()),
+            ("bonds", forcefield.bonds.len()),
+        ] {
+            let given = counts.get(name).copied().unwrap_or(0);
+            ensure!(
+                given == found,
+                "Expected {} {} but found {}",
+                given,
+                name,
+                found
+            );
+        }
+
+        Ok(forcefield)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_prms_test() {
+        let forcefield = ForceField::from_file("tests/test_bonds.prms").unwrap();
+        assert_eq!(forcefield.atoms.len(), 7);
+        assert_eq!(forcefield.atoms[0].atomic_number, 8);
+        assert_eq!(forcefield.atoms[6].atomic_number, 29);
+        assert_eq!(forcefield.atoms[1].charge, 0.417);
+        assert_eq!(forcefield.atoms[1].rmin_half, 0.2245);
+        assert_eq!(
+            forcefield.bonds,
+            vec![(0, 1), (0, 2), (3, 4), (3, 5), (0, 6)]
+        );
+
+        assert!("$atoms\n1\n1 X 0.0 0.0 0.0".parse::<ForceField>().is_err());
+        assert!("$atoms\n1\n1 O 0.0 0.0 0.0\n$bonds\n1\n1 2"
+            .parse::<ForceField>()
+            .is_err());
+        assert!("$atoms\n1\n2 O 0.0 0.0 0.0".parse::<ForceField>().is_err());
+        assert!("$atoms\n2\n1 O 0.0 0.0 0.0".parse::<ForceField>().is_err());
+        // Atom types and further columns cannot be told apart from elements
+        assert!("$atoms\n1\n1 CA 6 12.011 0.0 0.0 0.0"
+            .parse::<ForceField>()
+            .is_err());
+        assert!("$atoms\n1\n1 O 0.0 0.0".parse::<ForceField>().is_err());
+    }
+}
@@ -0,0 +1,87 @@
+// Residue names used by Amber, CHARMM and the PDB for classifying residues.
+
+pub const PROTEIN_RESIDUES: &[&str] = &[
+    // Standard amino acids
+    "ALA", "ARG", "ASN", "ASP", "CYS", "GLN", "GLU", "GLY", "HIS", "ILE", "LEU", "LYS", "MET",
+    "PHE", "PRO", "SER", "THR", "TRP", "TYR", "VAL", // Amber protonation states
+    "HIE", "HID", "HIP", "CYX", "CYM", "ASH", "GLH", "LYN", // CHARMM protonation states
+    "HSD", "HSE", "HSP", "ASPP", "GLUP", "LSN",
+    // Non-standard amino acids common in the PDB
+    "MSE", "SEC", "PYL",
+];
+
+pub const NUCLEIC_RESIDUES: &[&str] = &[
+    // PDB and Amber DNA
+    "DA", "DC", "DG", "DT", "DU", // PDB RNA
+    "A", "C", "G", "U", "I", // Amber RNA
+    "RA", "RC", "RG", "RU", // CHARMM
+    "ADE", "CYT", "GUA", "THY", "URA",
+];
+
+pub const WATER_RESIDUES: &[&str] = &[
+    "WAT", "HOH", "H2O", "DOD", "SOL", "TIP", "TIP3", "TIP3P", "TIP4", "TIP4P", "TIP5", "TIP5P",
+    "TP3", "T3P", "T4P", "T5P", "SPC", "SPCE",
+];
+
+pub const ION_RESIDUES: &[&str] = &[
+    // PDB and Amber
+    "LI", "NA", "K", "RB", "CS", "MG", "CA", "SR", "BA", "MN", "FE", "FE2", "CO", "NI", "CU", "CU1",
+    "ZN", "CD", "HG", "F", "CL", "BR", "IOD", "LI+", "NA+", "K+", "RB+", "CS+", "MG2", "CA2",
+    "ZN2", "CU2", "FE3", "F-", "CL-", "BR-", "I-", // CHARMM
+    "LIT", "SOD", "POT", "RUB", "CES", "CAL", "BAR", "CLA",
+];
+
+/// Checks whether the residue name is a known amino acid. Amber N- and C-terminal
+/// variants (e.g. 'NALA', 'CALA') are recognized as well.
+pub fn is_protein(name: &str) -> bool {
+    let name = name.to_uppercase();
+    PROTEIN_RESIDUES.contains(&name.as_str())
+        || (name.is_ascii()
+            && name.len() == 4
+            && (name.starts_with('N') || name.starts_with('C'))
+            && PROTEIN_RESIDUES.contains(&&name[1..]))
+}
+
+/// Checks whether the residue name is a known nucleotide. Amber 5'- and 3'-terminal as well
+/// as free nucleoside variants (e.g. 'DA5', 'RU3', 'DGN') are recognized as well.
+pub fn is_nucleic(name: &str) -> bool {
+    let name = name.to_uppercase();
+    NUCLEIC_RESIDUES.contains(&name.as_str())
+        || (name.is_ascii()
+            && (name.ends_with('5') || name.ends_with('3') || name.ends_with('N'))
+            && NUCLEIC_RESIDUES.contains(&&name[..name.len() - 1]))
+}
+
+pub fn is_water(name: &str) -> bool {
+    WATER_RESIDUES.contains(&name.to_uppercase().as_str())
+}
+
+pub fn is_ion(name: &str) -> bool {
+    ION_RESIDUES.contains(&name.to_uppercase().as_str())
+}
+
+/// Ligands are all residues that are neither protein, nucleic acid, water nor ions.
+pub fn is_ligand(name: &str) -> bool {
+    !(is_protein(name) || is_nucleic(name) || is_water(name) || is_ion(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_residues_test() {
+        assert!(is_protein("hie"));
+        assert!(is_protein("NALA"));
+        assert!(is_protein("HSD"));
+        assert!(!is_protein("WAT"));
+        assert!(is_nucleic("DA5"));
+        assert!(is_nucleic("ru3"));
+        assert!(!is_nucleic("CA"));
+        assert!(is_water("TIP3"));
+        assert!(is_ion("Na+"));
+        assert!(is_ion("SOD"));
+        assert!(is_ligand("4YB"));
+        assert!(!is_ligand("HOH"));
+    }
+}
@@ -52,7 +52,8 @@ impl Revertable {
         match self {
             Revertable::One(e) => e.undo(pdb),
             Revertable::Many(e) => {
-                for op in e {
+                // Undone in reverse so that operations on the same region cancel out correctly
+                for op in e.iter().rev() {
                     op.undo(pdb)
                 }
             }
@@ -115,3 +116,42 @@ impl Revertable {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Region;
+    use pdbtbx::StrictnessLevel;
+
+    #[test]
+    fn undo_many_test() {
+        let (mut pdb, _) =
+            pdbtbx::open_pdb("tests/test_get_atomlist.pdb", StrictnessLevel::Strict).unwrap();
+        let active = |pdb: &pdbtbx::PDB| functions::get_atomlist(pdb, Region::Active).unwrap();
+        let previous = active(&pdb);
+        let replaced = vec![1, 2, 4, 5, 6, 8, 9, 11, 12];
+
+        // Atoms 1, 2, 5, 6, 8 and 9 are removed and added again
+        let edit_op = Revertable::Many(vec![
+            EditOp::ToRemove {
+                region: Region::Active,
+                atoms: functions::edit_atoms_checked(&mut pdb, &previous, "Remove", Region::Active)
+                    .unwrap(),
+            },
+            EditOp::ToAdd {
+                region: Region::Active,
+                atoms: functions::edit_atoms_checked(&mut pdb, &replaced, "Add", Region::Active)
+                    .unwrap(),
+            },
+        ]);
+        assert_eq!(active(&pdb), replaced);
+
+        // Operations are undone in reverse order
+        edit_op.undo(&mut pdb);
+        assert_eq!(active(&pdb), previous);
+        edit_op.redo(&mut pdb);
+        assert_eq!(active(&pdb), replaced);
+        edit_op.undo(&mut pdb);
+        assert_eq!(active(&pdb), previous);
+    }
+}
@@ -0,0 +1,413 @@
+// Parser for CHARMM residue topology files (RTF).
+
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+
+use crate::functions::protein_base_name;
+use crate::residue_names::is_water;
+
+/// CHARMM36 all-hydrogen protein topology shipped with pdbman.
+const CHARMM36_PROTEIN: &str = include_str!("../assets/top_all36_prot.rtf");
+
+/// Elements of atom types without element column in MASS records, identified by their mass.
+const ELEMENT_MASSES: &[(&str, f64)] = &[
+    ("H", 1.008),
+    ("C", 12.011),
+    ("N", 14.007),
+    ("O", 15.999),
+    ("P", 30.974),
+    ("S", 32.06),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateAtom {
+    pub name: String,
+    pub atom_type: String,
+    pub charge: f64,
+}
+
+/// Residue (RESI) or patch (PRES) of a topology file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Template {
+    pub name: String,
+    pub atoms: Vec<TemplateAtom>,
+    /// Bonds given by atom names. Names prefixed with '-' or '+' refer to atoms of the
+    /// previous or next residue.
+    pub bonds: Vec<(String, String)>,
+    deleted: Vec<String>,
+}
+
+impl Template {
+    pub fn atom(&self, name: &str) -> Option<&TemplateAtom> {
+        self.atoms.iter().find(|a| a.name == name)
+    }
+
+    /// Applies a patch by replacing or adding its atoms and removing the deleted ones.
+    /// Residue prefixes of patches for several residues (e.g. '1CB' in DISU) are ignored.
+    fn apply_patch(&mut self, patch: &Template) {
+        let strip = |name: &str| {
+            name.trim_start_matches(|c: char| c.is_ascii_digit())
+                .to_owned()
+        };
+
+        for deleted in &patch.deleted {
+            let deleted = strip(deleted);
+            self.atoms.retain(|a| a.name != deleted);
+            self.bonds.retain(|(a, b)| *a != deleted && *b != deleted);
+        }
+        for atom in &patch.atoms {
+            let atom = TemplateAtom {
+                name: strip(&atom.name),
+                ..atom.clone()
+            };
+            match self.atoms.iter_mut().find(|a| a.name == atom.name) {
+                Some(a) => *a = atom,
+                None => self.atoms.push(atom),
+            }
+        }
+        let prefix =
+            |name: &str| name.len() - name.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        for (a, b) in &patch.bonds {
+            // Bonds between the patched residues are skipped
+            if a[..prefix(a)] != b[..prefix(b)] {
+                continue;
+            }
+            let (a, b) = (strip(a), strip(b));
+            if self.atom(&a).is_some() && self.atom(&b).is_some() {
+                self.bonds.push((a, b))
+            }
+        }
+        self.name = format!("{}+{}", self.name, patch.name);
+    }
+
+    /// Returns the template atom corresponding to an atom of a residue with the given atom
+    /// names. Common PDB and Amber naming differences to CHARMM are taken into account.
+    pub fn match_atom(&self, name: &str, residue_atoms: &[&str]) -> Option<&TemplateAtom> {
+        if let Some(a) = self.atom(name) {
+            return Some(a);
+        }
+        // Single atoms like ions are matched regardless of their names
+        if self.atoms.len() == 1 && residue_atoms.len() == 1 {
+            return self.atoms.first();
+        }
+
+        let candidate = match name {
+            "H" => "HN".to_owned(),
+            "H1" | "H2" | "H3" if self.atom("HT1").is_some() => format!("HT{}", &name[1..]),
+            "OXT" => "OT2".to_owned(),
+            "O" if self.atom("OT1").is_some() => "OT1".to_owned(),
+            "O" if self.atom("OH2").is_some() => "OH2".to_owned(),
+            "CD1" if self.atom("CD").is_some() => "CD".to_owned(),
+            "HD11" | "HD12" | "HD13" if self.atom("CD").is_some() => format!("HD{}", &name[3..]),
+            // Hydroxyl and thiol hydrogens, e.g. 'HG' of serine is 'HG1' in CHARMM
+            _ if self.atom(&format!("{}1", name)).is_some() => format!("{}1", name),
+            // Amber numbers methylene hydrogens 2 and 3, CHARMM 1 and 2
+            _ if name.ends_with('3') => {
+                let renamed = format!("{}1", &name[..name.len() - 1]);
+                if residue_atoms.contains(&renamed.as_str()) {
+                    return None;
+                }
+                renamed
+            }
+            _ => return None,
+        };
+        self.atom(&candidate)
+    }
+}
+
+/// Residue templates, patches and atom types of a topology file.
+#[derive(Debug, Default)]
+pub struct Topology {
+    elements: HashMap<String, String>,
+    templates: HashMap<String, Template>,
+    patches: HashMap<String, Template>,
+    /// Malformed records that were skipped while parsing.
+    pub warnings: Vec<String>,
+}
+
+impl Topology {
+    /// Returns the CHARMM36 protein topology shipped with pdbman.
+    pub fn charmm36_protein() -> Topology {
+        CHARMM36_PROTEIN
+            .parse()
+            .expect("Shipped topology file must be valid")
+    }
+
+    pub fn from_file(path: &str) -> Result<Topology, anyhow::Error> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read topology file: {}", path))?;
+        content
+            .parse()
+            .with_context(|| format!("Failed to parse topology file: {}", path))
+    }
+
+    fn insert(&mut self, template: Template, is_patch: bool) {
+        let map = if is_patch {
+            &mut self.patches
+        } else {
+            &mut self.templates
+        };
+        map.insert(template.name.clone(), template);
+    }
+
+    /// Returns the element of an atom type as given in the MASS records.
+    pub fn element(&self, atom_type: &str) -> Option<&str> {
+        self.elements.get(atom_type).map(String::as_str)
+    }
+
+    /// Returns the template for a residue, taking common Amber and PDB residue names into
+    /// account. Terminal patches are applied if the residue is an N- or C-terminus.
+    pub fn residue_template(&self, residue: &str, nterm: bool, cterm: bool) -> Option<Template> {
+        let residue = residue.to_uppercase();
+        let (name, patch) = match residue.as_str() {
+            "HIS" | "HID" => ("HSD", None),
+            "HIE" => ("HSE", None),
+            "HIP" => ("HSP", None),
+            "CYX" => ("CYS", Some("DISU")),
+            "ASH" | "ASPP" => ("ASP", Some("ASPP")),
+            "GLH" | "GLUP" => ("GLU", Some("GLUP")),
+            "LYN" | "LSN" => ("LYS", Some("LSN")),
+            "NA+" => ("SOD", None),
+            "CL" | "CL-" => ("CLA", None),
+            r if is_water(r) => ("TIP3", None),
+            // Amber terminal residues, e.g. 'NALA'
+            r if protein_base_name(r) != r && !self.templates.contains_key(r) => {
+                return self.residue_template(
+                    protein_base_name(r),
+                    nterm || r.starts_with('N'),
+                    cterm || r.starts_with('C'),
+                );
+            }
+            r => (r, None),
+        };
+
+        let mut template = self.templates.get(name)?.clone();
+        if let Some(p) = patch.and_then(|p| self.patches.get(p)) {
+            template.apply_patch(p)
+        }
+        if nterm {
+            let patch = match name {
+                "GLY" => "GLYP",
+                "PRO" => "PROP",
+                _ => "NTER",
+            };
+            template.apply_patch(self.patches.get(patch)?)
+        }
+        if cterm {
+            template.apply_patch(self.patches.get("CTER")?)
+        }
+        Some(template)
+    }
+}
+
+impl FromStr for Topology {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut topology = Topology::default();
+        // The template currently read and whether it is a patch
+        let mut current: Option<(Template, bool)> = None;
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('!').next().unwrap_or("");
+            if line.starts_with('*') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let keyword = match fields.first() {
+                Some(k) => k.to_uppercase(),
+                None => continue,
+            };
+
+            // CHARMM keywords may be abbreviated to four characters
+            match keyword.get(..4).unwrap_or(&keyword) {
+                "MASS" => {
+                    ensure!(fields.len() >= 4, "Invalid MASS record in line {}", i + 1);
+                    let element = match fields.get(4) {
+                        Some(e) => e.to_uppercase(),
+                        None => {
+                            let mass: f64 = fields[3]
+                                .parse()
+                                .with_context(|| format!("Invalid mass in line {}", i + 1))?;
+                            match ELEMENT_MASSES.iter().find(|(_, m)| (m - mass).abs() < 0.1) {
+                                Some((e, _)) => e.to_string(),
+                                None => continue,
+                            }
+                        }
+                    };
+                    topology.elements.insert(fields[2].to_uppercase(), element);
+                }
+                "RESI" | "PRES" => {
+                    ensure!(fields.len() >= 2, "Missing residue name in line {}", i + 1);
+                    if let Some((t, is_patch)) = current.take() {
+                        topology.insert(t, is_patch);
+                    }
+                    let template = Template {
+                        name: fields[1].to_uppercase(),
+                        ..Template::default()
+                    };
+                    current = Some((template, keyword.starts_with("PRES")));
+                }
+                "ATOM" => {
+                    let (template, _) = current.as_mut().with_context(|| {
+                        format!("ATOM record outside residue in line {}", i + 1)
+                    })?;
+                    ensure!(fields.len() >= 4, "Invalid ATOM record in line {}", i + 1);
+                    template.atoms.push(TemplateAtom {
+                        name: fields[1].to_uppercase(),
+                        atom_type: fields[2].to_uppercase(),
+                        charge: fields[3]
+                            .parse()
+                            .with_context(|| format!("Invalid charge in line {}", i + 1))?,
+                    });
+                }
+                "BOND" | "DOUB" | "TRIP" => {
+                    if let Some((template, is_patch)) = current.as_mut() {
+                        // Tokens that are no atoms of the residue, e.g. the stray 'v' in GLU of
+                        // the CHARMM36 protein topology, are skipped. Patches may refer to atoms
+                        // of the patched residues and are not checked.
+                        let mut names: Vec<String> = Vec::new();
+                        for field in &fields[1..] {
+                            let name = field.to_uppercase();
+                            if *is_patch
+                                || template.atom(name.trim_start_matches(['+', '-'])).is_some()
+                            {
+                                names.push(name)
+                            } else {
+                                topology.warnings.push(format!(
+                                    "Skipped unknown atom '{}' in bond record in line {}",
+                                    field,
+                                    i + 1
+                                ))
+                            }
+                        }
+                        if names.len() % 2 == 1 {
+                            topology.warnings.push(format!(
+                                "Skipped unpaired atom '{}' in bond record in line {}",
+                                names.pop().unwrap_or_default(),
+                                i + 1
+                            ))
+                        }
+                        template
+                            .bonds
+                            .extend(names.chunks(2).map(|b| (b[0].clone(), b[1].clone())));
+                    }
+                }
+                "DELE" => {
+                    if let (Some((template, _)), Some(name)) = (current.as_mut(), fields.get(2)) {
+                        if fields[1].eq_ignore_ascii_case("ATOM") {
+                            template.deleted.push(name.to_uppercase())
+                        }
+                    }
+                }
+                "END" => break,
+                _ => {}
+            }
+        }
+
+        if let Some((t, is_patch)) = current.take() {
+            topology.insert(t, is_patch);
+        }
+        ensure!(
+            !topology.templates.is_empty(),
+            "No residues found in topology file"
+        );
+
+        Ok(topology)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn charge(template: &Template) -> f64 {
+        template.atoms.iter().map(|a| a.charge).sum()
+    }
+
+    #[test]
+    fn malformed_bond_test() {
+        let topology: Topology = "
+MASS 1 H 1.008 H
+RESI GLU -1.00
+ATOM N N 0.0
+ATOM CA C 0.0
+ATOM HA H 0.0
+ATOM CB C 0.0
+ATOM HB1 H 0.0
+ATOM HB2 H 0.0
+ATOM CG C 0.0
+ATOM HG1 H 0.0
+ATOM HG2 H 0.0
+ATOM C C 0.0
+BOND C  +N v CA HA  CB HB1 CB  HB2 CG  HG1   
+BOND CG HG2 CB
+END"
+        .parse()
+        .unwrap();
+
+        let glu = topology.residue_template("GLU", false, false).unwrap();
+        assert_eq!(glu.bonds.len(), 6);
+        assert!(glu.bonds.contains(&("C".to_owned(), "+N".to_owned())));
+        assert!(glu.bonds.contains(&("CA".to_owned(), "HA".to_owned())));
+        assert!(glu.bonds.contains(&("CG".to_owned(), "HG2".to_owned())));
+        assert_eq!(
+            topology.warnings,
+            vec![
+                "Skipped unknown atom 'v' in bond record in line 14",
+                "Skipped unpaired atom 'CB' in bond record in line 15"
+            ]
+        );
+    }
+
+    #[test]
+    fn charmm36_test() {
+        let topology = Topology::charmm36_protein();
+        assert_eq!(topology.element("CT1"), Some("C"));
+        assert_eq!(topology.element("SOD"), Some("NA"));
+        assert_eq!(topology.warnings.len(), 1);
+
+        let ala = topology.residue_template("ALA", false, false).unwrap();
+        assert_eq!(ala.atoms.len(), 10);
+        assert_eq!(ala.atom("CB").unwrap().atom_type, "CT3");
+        assert!(ala.bonds.contains(&("C".to_owned(), "+N".to_owned())));
+        assert!(charge(&ala).abs() < 1e-6);
+
+        let glu = topology.residue_template("CGLU", false, false).unwrap();
+        assert_eq!(glu.name, "GLU+CTER");
+        assert!(glu.atom("O").is_none());
+        assert!((charge(&glu) + 2.0).abs() < 1e-6);
+
+        let hie = topology.residue_template("HIE", true, false).unwrap();
+        assert_eq!(hie.name, "HSE+NTER");
+        assert!((charge(&hie) - 1.0).abs() < 1e-6);
+        let names = ["N", "H1", "H2", "H3", "CA", "HB2", "HB3"];
+        assert_eq!(hie.match_atom("H2", &names).unwrap().name, "HT2");
+        assert_eq!(hie.match_atom("HB3", &names).unwrap().name, "HB1");
+        assert_eq!(hie.match_atom("HB2", &names).unwrap().name, "HB2");
+
+        let cyx = topology.residue_template("CYX", false, false).unwrap();
+        assert!(cyx.atom("HG1").is_none());
+        assert!(!cyx.bonds.contains(&("SG".to_owned(), "SG".to_owned())));
+        let ser = topology.residue_template("SER", false, false).unwrap();
+        assert_eq!(ser.match_atom("HG", &["OG", "HG"]).unwrap().name, "HG1");
+        assert!(topology.residue_template("XYZ", false, false).is_none());
+
+        // Terminal Amber names keep the termini given by the caller, other names are not stripped
+        let ala = topology.residue_template("NALA", false, true).unwrap();
+        assert_eq!(ala.name, "ALA+NTER+CTER");
+        assert!(topology.residue_template("XALA", false, false).is_none());
+    }
+
+    #[test]
+    fn parse_error_test() {
+        assert!("RESI ALA 0.00\nATOM CA CT1 abc"
+            .parse::<Topology>()
+            .is_err());
+        assert!("ATOM CA CT1 0.07".parse::<Topology>().is_err());
+        assert!("* Title only".parse::<Topology>().is_err());
+    }
+}
@@ -1,8 +1,15 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
 use colored::Colorize;
 use itertools::Itertools;
 use pest::error::{ErrorVariant, InputLocation};
+use pest::iterators::Pair;
 use pest::Parser;
 
+use crate::functions::SecondaryStructure;
+use crate::options::Region;
+
 #[derive(Parser)]
 #[grammar = "selection/selection.pest"]
 pub struct SelectionParser;
@@ -13,153 +20,495 @@ pub enum Conjunction {
     Or,
 }
 
+/// Residue as given in a 'resid' selection. If no chain is given, residues of all chains
+/// match. If no insertion code is given, residues with any insertion code match.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ResidueSpec<'a> {
+    pub chain: Option<&'a str>,
+    pub serial: isize,
+    pub insertion_code: Option<&'a str>,
+}
+
+impl<'a> ResidueSpec<'a> {
+    pub fn matches(&self, chain: &str, serial: isize, insertion_code: Option<&str>) -> bool {
+        self.serial == serial
+            && (self.chain.is_none() || self.chain == Some(chain))
+            && match (self.insertion_code, insertion_code) {
+                (None, _) => true,
+                (Some(i), Some(j)) => i.eq_ignore_ascii_case(j),
+                (Some(_), None) => false,
+            }
+    }
+}
+
+impl<'a> fmt::Display for ResidueSpec<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(c) = self.chain {
+            write!(f, "{}:", c)?;
+        }
+        write!(f, "{}{}", self.serial, self.insertion_code.unwrap_or(""))
+    }
+}
+
+/// Numeric atom properties that can be compared against in selections.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Property {
+    X,
+    Y,
+    Z,
+    BFactor,
+    Occupancy,
+    /// Solvent accessible surface area in A^2 with the default probe radius
+    Sasa,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Comparator {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Comparator {
+    pub fn compare(&self, left: f64, right: f64) -> bool {
+        match self {
+            Comparator::Equal => left == right,
+            Comparator::NotEqual => left != right,
+            Comparator::Less => left < right,
+            Comparator::LessEqual => left <= right,
+            Comparator::Greater => left > right,
+            Comparator::GreaterEqual => left >= right,
+        }
+    }
+}
+
+/// Predefined selections of common parts of biomolecular systems.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Macro {
+    Protein,
+    Nucleic,
+    Water,
+    Ion,
+    Ligand,
+    Backbone,
+    Sidechain,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Selection<'a> {
     ID {
         atomlist: Vec<usize>,
-        invert: bool,
     },
     Name {
         atomlist: Vec<&'a str>,
-        invert: bool,
     },
     Resid {
-        reslist: Vec<isize>,
-        invert: bool,
+        reslist: Vec<ResidueSpec<'a>>,
     },
     Resname {
         reslist: Vec<&'a str>,
-        invert: bool,
+    },
+    Chain {
+        chainlist: Vec<&'a str>,
+    },
+    Element {
+        elementlist: Vec<&'a str>,
+    },
+    Hetero,
+    Comparison {
+        property: Property,
+        comparator: Comparator,
+        value: f64,
     },
     Sphere {
         origin: usize,
         radius: f64,
-        invert: bool,
     },
     ResSphere {
         origin: usize,
         radius: f64,
-        invert: bool,
     },
+    Region(Region),
+    MM,
+    SecondaryStructure(SecondaryStructure),
+    Macro(Macro),
+    Named(&'a str),
+}
+
+/// Named selections given by the user, mapping the name to the selection input.
+pub type Definitions = BTreeMap<String, String>;
+
+/// Tree of selections combined by conjunctions and negations. Precedence is given
+/// by the grammar (not > and > or), parentheses group sub-expressions.
+#[derive(Debug, PartialEq)]
+pub enum Expression<'a> {
+    Selection(Selection<'a>),
+    Not(Box<Expression<'a>>),
+    Conjunction(Box<Expression<'a>>, Conjunction, Box<Expression<'a>>),
+    Within {
+        radius: f64,
+        byres: bool,
+        inner: Box<Expression<'a>>,
+    },
+    Nearest {
+        count: usize,
+        byres: bool,
+        inner: Box<Expression<'a>>,
+    },
+    BondedTo(Box<Expression<'a>>),
+    NBonds {
+        bonds: usize,
+        inner: Box<Expression<'a>>,
+    },
+    Fragment(Box<Expression<'a>>),
+    MetalSite(Box<Expression<'a>>),
 }
 
-pub fn parse_selection(
-    input: &str,
-) -> Result<(Vec<Selection>, Vec<Conjunction>), pest::error::Error<Rule>> {
-    let success = SelectionParser::parse(Rule::selection_chain, input)?
+pub fn parse_selection(input: &str) -> Result<Expression, pest::error::Error<Rule>> {
+    let expression = SelectionParser::parse(Rule::selection_chain, input)?
         .next()
         .unwrap()
-        .into_inner();
-
-    let mut selections = Vec::new();
-    let mut conjunctions = Vec::new();
-
-    let mut invert = false;
-    for i in success {
-        match i.as_rule() {
-            Rule::selection => {
-                for j in i.into_inner() {
-                    match j.as_rule() {
-                        Rule::negate => invert = !j.as_str().is_empty(),
-                        Rule::namesel => {
-                            let mut pairs = j.into_inner();
-                            let name_str = pairs.next().unwrap().as_str();
-                            let name_list: Vec<&str> = pairs
-                                .next()
-                                .unwrap()
-                                .into_inner()
-                                .map(|pair| pair.as_str())
-                                .collect();
-                            selections.push(match name_str.to_lowercase().as_str() {
-                                "name" => Selection::Name {
-                                    atomlist: name_list,
-                                    invert,
-                                },
-                                "resn" | "resname" => Selection::Resname {
-                                    reslist: name_list,
-                                    invert,
-                                },
-                                _ => unreachable!(),
-                            })
-                        }
-                        Rule::idsel => {
-                            let mut pairs = j.into_inner();
-                            let num_str = pairs.next().unwrap().as_str();
-
-                            let num_elements = pairs.next().unwrap().into_inner();
-                            let mut num_vec = Vec::new();
-                            for num_pair in num_elements {
-                                if let Ok(r) = num_pair.as_str().parse::<usize>() {
-                                    num_vec.push(r)
-                                } else {
-                                    let mut range =
-                                        num_pair.into_inner().next().unwrap().into_inner();
-                                    let start =
-                                        range.next().unwrap().as_str().parse::<usize>().unwrap();
-
-                                    let end =
-                                        range.next().unwrap().as_str().parse::<usize>().unwrap();
-                                    if start < end {
-                                        num_vec.extend(start..=end)
-                                    } else {
-                                        num_vec.extend(end..=start)
-                                    }
-                                }
-                            }
-                            selections.push(match num_str.to_lowercase().as_str() {
-                                "id" => Selection::ID {
-                                    atomlist: num_vec,
-                                    invert,
-                                },
-                                "resid" => Selection::Resid {
-                                    reslist: num_vec.into_iter().map(|n| n as isize).collect(),
-                                    invert,
-                                },
-                                _ => unreachable!(),
-                            })
+        .into_inner()
+        .next()
+        .unwrap();
+
+    Ok(parse_expression(expression))
+}
+
+impl<'a> Expression<'a> {
+    /// Returns the names of all named selections referred to in the expression.
+    pub fn references(&self) -> Vec<&'a str> {
+        match self {
+            Expression::Selection(Selection::Named(name)) => vec![name],
+            Expression::Selection(_) => Vec::new(),
+            Expression::Not(e)
+            | Expression::Within { inner: e, .. }
+            | Expression::Nearest { inner: e, .. }
+            | Expression::BondedTo(e)
+            | Expression::NBonds { inner: e, .. }
+            | Expression::Fragment(e)
+            | Expression::MetalSite(e) => e.references(),
+            Expression::Conjunction(left, _, right) => {
+                let mut names = left.references();
+                names.extend(right.references());
+                names
+            }
+        }
+    }
+}
+
+/// Returns the names of all named selections the given selection depends on, directly or
+/// indirectly through other named selections. Names that are not defined are skipped.
+pub fn dependencies<'a>(input: &'a str, definitions: &'a Definitions) -> Vec<&'a str> {
+    let mut dependencies: Vec<&str> = Vec::new();
+    let mut stack = match parse_selection(input) {
+        Ok(e) => e.references(),
+        Err(_) => return dependencies,
+    };
+
+    while let Some(name) = stack.pop() {
+        if dependencies.contains(&name) {
+            continue;
+        }
+        dependencies.push(name);
+        if let Some(Ok(e)) = definitions.get(name).map(|s| parse_selection(s)) {
+            stack.extend(e.references());
+        }
+    }
+    dependencies
+}
+
+/// Orders named selections such that every selection comes after the ones it refers to.
+pub fn ordered_definitions(definitions: &Definitions) -> Vec<(&str, &str)> {
+    fn visit<'a>(
+        name: &'a str,
+        definitions: &'a Definitions,
+        ordered: &mut Vec<(&'a str, &'a str)>,
+    ) {
+        if ordered.iter().any(|(n, _)| *n == name) {
+            return;
+        }
+        if let Some((key, value)) = definitions.get_key_value(name) {
+            if let Ok(e) = parse_selection(value) {
+                e.references()
+                    .into_iter()
+                    .for_each(|r| visit(r, definitions, ordered));
+            }
+            ordered.push((key, value));
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(definitions.len());
+    definitions
+        .keys()
+        .for_each(|name| visit(name, definitions, &mut ordered));
+    ordered
+}
+
+/// Folds the 'or'-separated parts of an expression into a left-associative tree.
+fn parse_expression(pair: Pair<Rule>) -> Expression {
+    let mut and_chains = pair.into_inner().filter(|p| p.as_rule() == Rule::and_chain);
+    let initial = parse_and_chain(and_chains.next().unwrap());
+
+    and_chains.fold(initial, |acc, p| {
+        Expression::Conjunction(Box::new(acc), Conjunction::Or, Box::new(parse_and_chain(p)))
+    })
+}
+
+/// Folds the 'and'-separated selections of an expression into a left-associative tree.
+fn parse_and_chain(pair: Pair<Rule>) -> Expression {
+    let mut terms = pair.into_inner().filter(|p| p.as_rule() == Rule::selection);
+    let initial = parse_term(terms.next().unwrap());
+
+    terms.fold(initial, |acc, p| {
+        Expression::Conjunction(Box::new(acc), Conjunction::And, Box::new(parse_term(p)))
+    })
+}
+
+/// Parses a single, possibly negated, selection or parenthesized group.
+fn parse_term(pair: Pair<Rule>) -> Expression {
+    let mut pairs = pair.into_inner();
+    let invert = !pairs.next().unwrap().as_str().is_empty();
+    let inner = pairs.next().unwrap();
+
+    let expression = match inner.as_rule() {
+        Rule::group => parse_expression(inner.into_inner().next().unwrap()),
+        Rule::within => {
+            let mut byres = false;
+            let mut radius = 0.0;
+            let mut within_expression = None;
+
+            for p in inner.into_inner() {
+                match p.as_rule() {
+                    Rule::byres_str => byres = true,
+                    Rule::within_str => {}
+                    Rule::radius => radius = p.as_str().parse().unwrap(),
+                    Rule::selection => within_expression = Some(parse_term(p)),
+                    _ => unreachable!(),
+                }
+            }
+
+            Expression::Within {
+                radius,
+                byres,
+                inner: Box::new(within_expression.unwrap()),
+            }
+        }
+        Rule::nearest => {
+            let mut pairs = inner.into_inner().skip(1);
+            let count = pairs.next().unwrap().as_str().parse().unwrap();
+            let byres = !pairs.next().unwrap().as_str().eq_ignore_ascii_case("atoms");
+            Expression::Nearest {
+                count,
+                byres,
+                inner: Box::new(parse_term(pairs.next().unwrap())),
+            }
+        }
+        Rule::bondedto => {
+            Expression::BondedTo(Box::new(parse_term(inner.into_inner().nth(1).unwrap())))
+        }
+        Rule::nbonds => {
+            let mut pairs = inner.into_inner().skip(1);
+            let bonds = pairs.next().unwrap().as_str().parse().unwrap();
+            Expression::NBonds {
+                bonds,
+                inner: Box::new(parse_term(pairs.next().unwrap())),
+            }
+        }
+        Rule::fragment => {
+            Expression::Fragment(Box::new(parse_term(inner.into_inner().nth(1).unwrap())))
+        }
+        Rule::metalsite => {
+            Expression::MetalSite(Box::new(parse_term(inner.into_inner().nth(1).unwrap())))
+        }
+        _ => Expression::Selection(parse_keyword(inner)),
+    };
+
+    if invert {
+        Expression::Not(Box::new(expression))
+    } else {
+        expression
+    }
+}
+
+/// Parses a keyword-driven selection like 'resid 1-4' into a Selection.
+fn parse_keyword(pair: Pair<Rule>) -> Selection {
+    match pair.as_rule() {
+        Rule::namesel => {
+            let mut pairs = pair.into_inner();
+            let name_str = pairs.next().unwrap().as_str();
+            let name_list: Vec<&str> = pairs
+                .next()
+                .unwrap()
+                .into_inner()
+                .map(|pair| pair.as_str())
+                .collect();
+            match name_str.to_lowercase().as_str() {
+                "name" => Selection::Name {
+                    atomlist: name_list,
+                },
+                "resn" | "resname" => Selection::Resname { reslist: name_list },
+                _ => unreachable!(),
+            }
+        }
+        Rule::chainsel => Selection::Chain {
+            chainlist: pair
+                .into_inner()
+                .nth(1)
+                .unwrap()
+                .into_inner()
+                .map(|pair| pair.as_str())
+                .collect(),
+        },
+        Rule::elementsel => Selection::Element {
+            elementlist: pair
+                .into_inner()
+                .nth(1)
+                .unwrap()
+                .into_inner()
+                .map(|pair| pair.as_str())
+                .collect(),
+        },
+        Rule::heterosel => Selection::Hetero,
+        Rule::comparison => {
+            let mut pairs = pair.into_inner();
+            let property = match pairs.next().unwrap().as_str().to_lowercase().as_str() {
+                "x" => Property::X,
+                "y" => Property::Y,
+                "z" => Property::Z,
+                "bfactor" => Property::BFactor,
+                "occupancy" => Property::Occupancy,
+                "sasa" => Property::Sasa,
+                _ => unreachable!(),
+            };
+            let comparator = match pairs.next().unwrap().as_str() {
+                "==" => Comparator::Equal,
+                "!=" => Comparator::NotEqual,
+                "<" => Comparator::Less,
+                "<=" => Comparator::LessEqual,
+                ">" => Comparator::Greater,
+                ">=" => Comparator::GreaterEqual,
+                _ => unreachable!(),
+            };
+            Selection::Comparison {
+                property,
+                comparator,
+                value: pairs.next().unwrap().as_str().parse().unwrap(),
+            }
+        }
+        Rule::idsel => {
+            let num_elements = pair.into_inner().nth(1).unwrap().into_inner();
+            let mut num_vec = Vec::new();
+            for num_pair in num_elements {
+                if let Ok(r) = num_pair.as_str().parse::<usize>() {
+                    num_vec.push(r)
+                } else {
+                    let (start, end) = parse_range(num_pair.into_inner().next().unwrap());
+                    num_vec.extend(start..=end)
+                }
+            }
+            Selection::ID { atomlist: num_vec }
+        }
+        Rule::residsel => {
+            let res_elements = pair.into_inner().nth(1).unwrap().into_inner();
+            let mut res_vec = Vec::new();
+            for res_pair in res_elements {
+                let mut chain = None;
+                for k in res_pair.into_inner() {
+                    match k.as_rule() {
+                        Rule::chain_id => chain = Some(k.as_str()),
+                        Rule::range => {
+                            let (start, end) = parse_range(k);
+                            res_vec.extend((start..=end).map(|n| ResidueSpec {
+                                chain,
+                                serial: n as isize,
+                                insertion_code: None,
+                            }))
                         }
-                        Rule::sphere => {
-                            let mut pairs = j.into_inner();
-                            let sphere_str = pairs.next().unwrap().as_str();
-                            let mut values = pairs.next().unwrap().into_inner();
-                            let origin: usize = values.next().unwrap().as_str().parse().unwrap();
-                            let radius: f64 = values.next().unwrap().as_str().parse().unwrap();
-
-                            selections.push(match sphere_str.to_lowercase().as_str() {
-                                "s" | "sphere" => Selection::Sphere {
-                                    origin,
-                                    radius,
-                                    invert,
-                                },
-                                "rs" | "ressphere" => Selection::ResSphere {
-                                    origin,
-                                    radius,
-                                    invert,
-                                },
-                                _ => unreachable!(),
+                        Rule::resid_single => {
+                            let mut single = k.into_inner();
+                            res_vec.push(ResidueSpec {
+                                chain,
+                                serial: single.next().unwrap().as_str().parse::<isize>().unwrap(),
+                                insertion_code: single.next().map(|i| i.as_str()),
                             })
                         }
                         _ => unreachable!(),
                     }
                 }
             }
-            Rule::conjunction => match i.as_str().to_lowercase().as_str() {
-                "and" | "&" => conjunctions.push(Conjunction::And),
-                "or" | "|" => conjunctions.push(Conjunction::Or),
+            Selection::Resid { reslist: res_vec }
+        }
+        Rule::sphere => {
+            let mut pairs = pair.into_inner();
+            let sphere_str = pairs.next().unwrap().as_str();
+            let mut values = pairs.next().unwrap().into_inner();
+            let origin: usize = values.next().unwrap().as_str().parse().unwrap();
+            let radius: f64 = values.next().unwrap().as_str().parse().unwrap();
+
+            match sphere_str.to_lowercase().as_str() {
+                "s" | "sphere" => Selection::Sphere { origin, radius },
+                "rs" | "ressphere" => Selection::ResSphere { origin, radius },
                 _ => unreachable!(),
-            },
-            Rule::EOI => {}
-            _ => unreachable!(),
+            }
         }
+        Rule::regionsel => match pair.as_str().to_lowercase().as_str() {
+            "qm1" => Selection::Region(Region::QM1),
+            "qm2" => Selection::Region(Region::QM2),
+            "active" => Selection::Region(Region::Active),
+            "mm" => Selection::MM,
+            _ => unreachable!(),
+        },
+        Rule::macrosel => Selection::Macro(match pair.as_str().to_lowercase().as_str() {
+            "protein" => Macro::Protein,
+            "nucleic" => Macro::Nucleic,
+            "water" => Macro::Water,
+            "ion" => Macro::Ion,
+            "ligand" => Macro::Ligand,
+            "backbone" => Macro::Backbone,
+            "sidechain" => Macro::Sidechain,
+            _ => unreachable!(),
+        }),
+        Rule::sssel => Selection::SecondaryStructure(
+            match pair
+                .into_inner()
+                .nth(1)
+                .unwrap()
+                .as_str()
+                .to_lowercase()
+                .as_str()
+            {
+                "helix" => SecondaryStructure::Helix,
+                "strand" => SecondaryStructure::Strand,
+                "turn" => SecondaryStructure::Turn,
+                "coil" => SecondaryStructure::Coil,
+                _ => unreachable!(),
+            },
+        ),
+        Rule::namedsel => Selection::Named(pair.into_inner().next().unwrap().as_str()),
+        _ => unreachable!(),
     }
+}
 
-    Ok((selections, conjunctions))
+/// Returns start and end of a range of numbers, ordered ascendingly.
+fn parse_range(pair: Pair<Rule>) -> (usize, usize) {
+    let mut range = pair.into_inner();
+    let start = range.next().unwrap().as_str().parse::<usize>().unwrap();
+    let end = range.next().unwrap().as_str().parse::<usize>().unwrap();
+    if start < end {
+        (start, end)
+    } else {
+        (end, start)
+    }
 }
 
 pub fn convert_result<'a>(
-    input: Result<(Vec<Selection<'a>>, Vec<Conjunction>), pest::error::Error<Rule>>,
+    input: Result<Expression<'a>, pest::error::Error<Rule>>,
     text: &'a str,
-) -> Result<(Vec<Selection<'a>>, Vec<Conjunction>), anyhow::Error> {
+) -> Result<Expression<'a>, anyhow::Error> {
     let mut err_string = String::new();
 
     match input {
@@ -185,14 +534,25 @@ pub fn convert_result<'a>(
                             .map(|rule| match rule {
                                 Rule::EOI => "the end of input",
                                 Rule::selection => {
-                                    "selection keyword: 'id/name/resid/resname/sphere/ressphere'"
+                                    "selection keyword: 'id/name/resid/resname/chain/element/hetero/sphere/ressphere/ss/qm1/qm2/active/mm/protein/nucleic/water/ion/ligand/backbone/sidechain/within/nearest/bondedto/nbonds/fragment/metalsite/@name'"
                                 }
-                                Rule::conjunction => "conjunction: 'and'/'or'",
+                                Rule::and_op | Rule::or_op => "conjunction: 'and'/'or'",
+                                Rule::group => "parenthesized selection",
                                 Rule::range => "range of numbers, e.g. '1-4'",
                                 Rule::sphere_values => "sphere values: origin atom ID and radius",
                                 Rule::origin => "origin atom ID",
-                                Rule::radius => "sphere radius",
-                                Rule::namelist_element => "name of atom or residue",
+                                Rule::radius => "radius",
+                                Rule::namelist_element => "name of atom, residue, chain or element",
+                                Rule::comparator => "comparison operator: '=='/'!='/'<'/'<='/'>'/'>='",
+                                Rule::number => "number",
+                                Rule::selection_name => "name of a defined selection",
+                                Rule::bond_count => "number of bonds",
+                                Rule::nearest_count => "number of atoms or residues",
+                                Rule::nearest_target => "'atoms' or 'res(idues)'",
+                                Rule::ss_type => "secondary structure: 'helix'/'strand'/'turn'/'coil'",
+                                Rule::resid_element => {
+                                    "residue number, optionally with chain and insertion code, e.g. 'A:52A'"
+                                }
                                 Rule::range_start => {
                                     "number or a range of numbers, e.g. '23' or '4-8'"
                                 }